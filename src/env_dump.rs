@@ -0,0 +1,183 @@
+//! The env-dump subcommand: print the variable set a render would see
+
+use crate::is_valid_name;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::env;
+
+/// Output formats supported by env-dump
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// NAME="value" lines
+    Dotenv,
+    /// A single JSON object
+    Json,
+    /// export NAME='value' lines
+    Shell,
+}
+
+/// Collect the environment overlaid with `overrides`, optionally filtered by
+/// name prefix, sorted by name
+/// Variables whose name or value is not valid unicode are skipped
+pub fn collect(
+    prefix: Option<&str>,
+    overrides: impl IntoIterator<Item = (String, String)>,
+) -> Vec<(String, String)> {
+    let mut vars: BTreeMap<String, String> = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .collect();
+    vars.extend(overrides);
+    vars.into_iter()
+        .filter(|(name, _)| prefix.is_none_or(|p| name.starts_with(p)))
+        .collect()
+}
+
+/// Render the variables in the given format
+/// The dotenv and shell formats skip variables whose name is not a valid
+/// variable name, as neither syntax can express them
+pub fn render(vars: &[(String, String)], format: Format) -> String {
+    let assignable = vars.iter().filter(|(name, _)| is_valid_name(name));
+    match format {
+        Format::Dotenv => assignable
+            .map(|(name, value)| format!("{}={}\n", name, dotenv_quote(value)))
+            .collect(),
+        Format::Shell => assignable
+            .map(|(name, value)| format!("export {}={}\n", name, shell_quote(value)))
+            .collect(),
+        Format::Json => {
+            let entries: Vec<String> = vars
+                .iter()
+                .map(|(name, value)| format!("  {}: {}", json_quote(name), json_quote(value)))
+                .collect();
+            if entries.is_empty() {
+                "{}\n".to_string()
+            } else {
+                format!("{{\n{}\n}}\n", entries.join(",\n"))
+            }
+        }
+    }
+}
+
+/// Double-quote a value for a .env file
+fn dotenv_quote(value: &str) -> String {
+    let mut result = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '$' => result.push_str("\\$"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            _ => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Single-quote a value for a POSIX shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quote a string as a JSON string literal
+fn json_quote(value: &str) -> String {
+    let mut result = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<(String, String)> {
+        vec![
+            ("APP_NAME".to_string(), "it's \"fine\"".to_string()),
+            ("APP_PATH".to_string(), "$HOME\\bin\nnext".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_collect_with_prefix() {
+        unsafe {
+            env::set_var("ENVDUMP_TEST_B", "2");
+            env::set_var("ENVDUMP_TEST_A", "1");
+        }
+        let vars = collect(Some("ENVDUMP_TEST_"), []);
+        assert_eq!(
+            vars,
+            vec![
+                ("ENVDUMP_TEST_A".to_string(), "1".to_string()),
+                ("ENVDUMP_TEST_B".to_string(), "2".to_string()),
+            ]
+        );
+        let overrides = [
+            ("ENVDUMP_TEST_B".to_string(), "file".to_string()),
+            ("ENVDUMP_TEST_C".to_string(), "3".to_string()),
+        ];
+        let vars = collect(Some("ENVDUMP_TEST_"), overrides);
+        assert_eq!(
+            vars,
+            vec![
+                ("ENVDUMP_TEST_A".to_string(), "1".to_string()),
+                ("ENVDUMP_TEST_B".to_string(), "file".to_string()),
+                ("ENVDUMP_TEST_C".to_string(), "3".to_string()),
+            ]
+        );
+        unsafe {
+            env::remove_var("ENVDUMP_TEST_A");
+            env::remove_var("ENVDUMP_TEST_B");
+        }
+    }
+
+    #[test]
+    fn test_render_dotenv() {
+        assert_eq!(
+            render(&sample(), Format::Dotenv),
+            "APP_NAME=\"it's \\\"fine\\\"\"\nAPP_PATH=\"\\$HOME\\\\bin\\nnext\"\n"
+        );
+    }
+
+    #[test]
+    fn test_render_shell() {
+        assert_eq!(
+            render(&sample(), Format::Shell),
+            "export APP_NAME='it'\\''s \"fine\"'\nexport APP_PATH='$HOME\\bin\nnext'\n"
+        );
+    }
+
+    #[test]
+    fn test_render_json() {
+        assert_eq!(
+            render(&sample(), Format::Json),
+            "{\n  \"APP_NAME\": \"it's \\\"fine\\\"\",\n  \"APP_PATH\": \"$HOME\\\\bin\\nnext\"\n}\n"
+        );
+        assert_eq!(render(&[], Format::Json), "{}\n");
+    }
+
+    #[test]
+    fn test_render_skips_invalid_names() {
+        let vars = vec![
+            ("my-var".to_string(), "1".to_string()),
+            ("OK".to_string(), "2".to_string()),
+        ];
+        assert_eq!(render(&vars, Format::Dotenv), "OK=\"2\"\n");
+        assert_eq!(render(&vars, Format::Shell), "export OK='2'\n");
+        assert_eq!(
+            render(&vars, Format::Json),
+            "{\n  \"my-var\": \"1\",\n  \"OK\": \"2\"\n}\n"
+        );
+    }
+}
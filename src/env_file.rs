@@ -0,0 +1,707 @@
+//! Parsing of files that assign variables
+
+use crate::is_valid_name;
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Dialects of variable assignment files
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// NAME=value lines as read by dotenv libraries
+    Dotenv,
+    /// systemd EnvironmentFile= syntax
+    Systemd,
+    /// Shell scripts of `export NAME=value` lines, parsed without being executed
+    Shell,
+}
+
+/// Variable assignments in the order they were made
+pub type Assignments = Vec<(String, String)>;
+
+/// How to settle a variable given different values by several sources
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OnConflict {
+    /// Fail, naming the variable and both sources
+    Error,
+    /// Use the last source, printing a warning naming the one that won
+    Warn,
+    /// Use the first source, the environment before any file
+    First,
+    /// Use the last source, the last file on the command line
+    Last,
+}
+
+/// Merge the variables of labelled sources, given in order after the
+/// environment, as seen through `environment`
+///
+/// Returns the variables whose value comes from one of the sources, so they
+/// take precedence over the environment, and a warning for each conflict
+/// settled under [`OnConflict::Warn`]. Only the last assignment of a variable
+/// within a source counts, and values are never shown, as they may be secret.
+pub fn merge(
+    sources: Vec<(String, Assignments)>,
+    environment: impl Fn(&str) -> Option<String>,
+    on_conflict: OnConflict,
+) -> Result<(Assignments, Vec<String>), String> {
+    // Current value of each variable and the index of its source, if not
+    // the environment
+    let mut vars: HashMap<String, (String, Option<usize>)> = HashMap::new();
+    let mut warnings = Vec::new();
+    let label = |source: Option<usize>| match source {
+        Some(index) => sources[index].0.clone(),
+        None => "the environment".to_string(),
+    };
+
+    for (index, (_, assignments)) in sources.iter().enumerate() {
+        let latest: BTreeMap<&String, &String> =
+            assignments.iter().map(|(name, value)| (name, value)).collect();
+        for (name, value) in latest {
+            let current = vars.get(name).cloned().or_else(|| {
+                environment(name).map(|value| (value, None))
+            });
+            let Some((current_value, current_source)) = current else {
+                vars.insert(name.clone(), (value.clone(), Some(index)));
+                continue;
+            };
+            if current_value != *value {
+                match on_conflict {
+                    OnConflict::Error => {
+                        return Err(format!(
+                            "variable '{}' has different values in {} and {}",
+                            name,
+                            label(current_source),
+                            label(Some(index))
+                        ));
+                    }
+                    OnConflict::Warn => warnings.push(format!(
+                        "variable '{}' from {} overrides {}",
+                        name,
+                        label(Some(index)),
+                        label(current_source)
+                    )),
+                    OnConflict::First => {
+                        vars.insert(name.clone(), (current_value, current_source));
+                        continue;
+                    }
+                    OnConflict::Last => {}
+                }
+            }
+            vars.insert(name.clone(), (value.clone(), Some(index)));
+        }
+    }
+
+    let mut merged: Assignments = vars
+        .into_iter()
+        .filter(|(_, (_, source))| source.is_some())
+        .map(|(name, (value, _))| (name, value))
+        .collect();
+    merged.sort();
+    Ok((merged, warnings))
+}
+
+/// Read and parse an assignment file, naming the file in errors
+pub fn load(path: &Path, format: Format) -> Result<Vec<(String, String)>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&content, format).map_err(|msg| format!("{}: {}", path.display(), msg))
+}
+
+/// Parse assignment file content in the given dialect
+pub fn parse(content: &str, format: Format) -> Result<Vec<(String, String)>, String> {
+    match format {
+        Format::Dotenv => parse_dotenv(content),
+        Format::Systemd => parse_systemd(content),
+        Format::Shell => parse_shell(content),
+    }
+}
+
+/// Parse dotenv content: NAME=value lines, '#' comments and blank lines
+///
+/// A line may start with `export `. Values may be unquoted (surrounding
+/// whitespace is trimmed), single-quoted (taken literally) or double-quoted
+/// (supporting \n, \r, \t, \\, \" and \$). Outside quotes, a '#' that
+/// starts the value or follows whitespace begins a comment.
+pub fn parse_dotenv(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line
+            .strip_prefix("export")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .unwrap_or(line);
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected NAME=value", index + 1))?;
+        let name = name.trim();
+        if !is_valid_name(name) {
+            return Err(format!("line {}: invalid variable name '{}'", index + 1, name));
+        }
+        let value = parse_dotenv_value(value.trim())
+            .map_err(|msg| format!("line {}: {}", index + 1, msg))?;
+        vars.push((name.to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// Unquote a single dotenv value, dropping any trailing comment
+fn parse_dotenv_value(raw: &str) -> Result<String, String> {
+    if let Some(rest) = raw.strip_prefix('\'') {
+        let (value, after) = rest
+            .split_once('\'')
+            .ok_or_else(|| "unterminated single quote".to_string())?;
+        return after_closing_quote(after).map(|_| value.to_string());
+    }
+    let Some(rest) = raw.strip_prefix('"') else {
+        let end = raw
+            .char_indices()
+            .find(|&(i, ch)| {
+                ch == '#' && (i == 0 || raw[..i].ends_with(char::is_whitespace))
+            })
+            .map_or(raw.len(), |(i, _)| i);
+        return Ok(raw[..end].trim_end().to_string());
+    };
+
+    let mut value = String::new();
+    let mut chars = rest.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => return after_closing_quote(chars.as_str()).map(|_| value),
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('r') => value.push('\r'),
+                Some('t') => value.push('\t'),
+                Some(other) => value.push(other),
+                None => break,
+            },
+            _ => value.push(ch),
+        }
+    }
+    Err("unterminated double quote".to_string())
+}
+
+/// Check that only whitespace or a comment follows a closing quote
+fn after_closing_quote(rest: &str) -> Result<(), String> {
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err("unexpected characters after closing quote".to_string())
+    }
+}
+
+/// Parser states for the systemd dialect
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    PreKey,
+    Key,
+    PreValue,
+    Value,
+    ValueEscape,
+    SingleQuote,
+    DoubleQuote,
+    DoubleQuoteEscape,
+    Comment,
+    CommentEscape,
+}
+
+/// Parse content following systemd's EnvironmentFile= rules
+///
+/// Lines starting with '#' or ';' are comments. A backslash at the end of a
+/// line continues it, also within comments. Single quotes are literal and may
+/// span lines; within double quotes a backslash only escapes '"', '\', '`'
+/// and '$' and is kept before any other character. Outside quotes a backslash
+/// escapes any character, and trailing whitespace is dropped. Quoted and
+/// unquoted parts may be concatenated.
+pub fn parse_systemd(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    let mut state = State::PreKey;
+    let mut key = String::new();
+    let mut value = String::new();
+    // Length of value without its trailing unquoted whitespace
+    let mut value_len = 0;
+    let mut line = 1;
+
+    let mut finish = |key: &mut String, value: &mut String, line: usize| {
+        let name = key.trim_end();
+        if !is_valid_name(name) {
+            return Err(format!("line {}: invalid variable name '{}'", line, name));
+        }
+        vars.push((name.to_string(), std::mem::take(value)));
+        key.clear();
+        Ok(())
+    };
+
+    // The end of the content acts as a final newline, except that, like
+    // systemd, a quoted value left open at the end is accepted as is
+    for ch in content.chars().map(Some).chain([None]) {
+        let ch = match ch {
+            Some(c) => c,
+            None if matches!(
+                state,
+                State::SingleQuote | State::DoubleQuote | State::DoubleQuoteEscape
+            ) =>
+            {
+                finish(&mut key, &mut value, line)?;
+                break;
+            }
+            None => '\n',
+        };
+        state = match state {
+            State::PreKey => match ch {
+                '#' | ';' => State::Comment,
+                c if c.is_whitespace() => State::PreKey,
+                c => {
+                    key.push(c);
+                    State::Key
+                }
+            },
+            State::Key => match ch {
+                '=' => {
+                    value.clear();
+                    value_len = 0;
+                    State::PreValue
+                }
+                '\n' => return Err(format!("line {}: expected NAME=value", line)),
+                c => {
+                    key.push(c);
+                    State::Key
+                }
+            },
+            State::PreValue => match ch {
+                '\n' => {
+                    finish(&mut key, &mut value, line)?;
+                    State::PreKey
+                }
+                '\'' => State::SingleQuote,
+                '"' => State::DoubleQuote,
+                '\\' => State::ValueEscape,
+                c if c.is_whitespace() => State::PreValue,
+                c => {
+                    value.push(c);
+                    value_len = value.len();
+                    State::Value
+                }
+            },
+            State::Value => match ch {
+                '\n' => {
+                    value.truncate(value_len);
+                    finish(&mut key, &mut value, line)?;
+                    State::PreKey
+                }
+                '\\' => State::ValueEscape,
+                c => {
+                    value.push(c);
+                    if !c.is_whitespace() {
+                        value_len = value.len();
+                    }
+                    State::Value
+                }
+            },
+            State::ValueEscape => {
+                if ch != '\n' {
+                    value.push(ch);
+                    value_len = value.len();
+                }
+                State::Value
+            }
+            State::SingleQuote => match ch {
+                '\'' => {
+                    value_len = value.len();
+                    State::PreValue
+                }
+                c => {
+                    value.push(c);
+                    State::SingleQuote
+                }
+            },
+            State::DoubleQuote => match ch {
+                '"' => {
+                    value_len = value.len();
+                    State::PreValue
+                }
+                '\\' => State::DoubleQuoteEscape,
+                c => {
+                    value.push(c);
+                    State::DoubleQuote
+                }
+            },
+            State::DoubleQuoteEscape => {
+                match ch {
+                    '\n' => {}
+                    '"' | '\\' | '`' | '$' => value.push(ch),
+                    c => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                }
+                State::DoubleQuote
+            }
+            State::Comment => match ch {
+                '\\' => State::CommentEscape,
+                '\n' => State::PreKey,
+                _ => State::Comment,
+            },
+            State::CommentEscape => State::Comment,
+        };
+        if ch == '\n' {
+            line += 1;
+        }
+    }
+    Ok(vars)
+}
+
+/// Character source for the shell dialect that keeps track of the line number
+struct ShellChars<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl ShellChars<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.chars.next();
+        if ch == Some('\n') {
+            self.line += 1;
+        }
+        ch
+    }
+
+    /// Skip spaces and tabs, but not newlines
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.next();
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(ch) = self.next() {
+            if ch == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("line {}: {}", self.line, msg)
+    }
+}
+
+/// Parse a shell script made of `export NAME=value` or `NAME=value` lines
+///
+/// Nothing is executed: values follow the shell's quoting rules (single
+/// quotes are literal, double quotes and bare backslashes escape characters)
+/// but `$` expansions and command substitutions are kept verbatim. Blank
+/// lines and '#' comments are skipped; any other statement is an error.
+pub fn parse_shell(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    let mut chars = ShellChars {
+        chars: content.chars().peekable(),
+        line: 1,
+    };
+
+    loop {
+        while chars.peek().is_some_and(char::is_whitespace) {
+            chars.next();
+        }
+        match chars.peek() {
+            None => break,
+            Some('#') => {
+                chars.skip_line();
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        let mut name = read_shell_name(&mut chars);
+        if name == "export" && matches!(chars.peek(), Some(' ' | '\t')) {
+            chars.skip_blanks();
+            name = read_shell_name(&mut chars);
+        }
+        if !is_valid_name(&name) || chars.peek() != Some('=') {
+            return Err(chars.error("expected NAME=value or export NAME=value"));
+        }
+        chars.next(); // consume '='
+        let value = read_shell_value(&mut chars)?;
+
+        chars.skip_blanks();
+        match chars.peek() {
+            None | Some('\n') | Some(';') => {
+                chars.next();
+            }
+            Some('#') => chars.skip_line(),
+            Some(_) => return Err(chars.error("unexpected characters after value")),
+        }
+        vars.push((name, value));
+    }
+
+    Ok(vars)
+}
+
+/// Read the characters that may form a variable name
+fn read_shell_name(chars: &mut ShellChars) -> String {
+    let mut name = String::new();
+    while let Some(ch) = chars.peek() {
+        if !(ch.is_ascii_alphanumeric() || ch == '_') {
+            break;
+        }
+        name.push(ch);
+        chars.next();
+    }
+    name
+}
+
+/// Read a single shell word, removing its quoting
+fn read_shell_value(chars: &mut ShellChars) -> Result<String, String> {
+    let mut value = String::new();
+    while let Some(ch) = chars.peek() {
+        if ch.is_whitespace() || ch == ';' {
+            break;
+        }
+        let start_line = chars.line;
+        chars.next();
+        match ch {
+            '\'' => loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some(c) => value.push(c),
+                    None => return Err(format!("line {}: unterminated single quote", start_line)),
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('\n') => {}
+                        Some(c @ ('$' | '`' | '"' | '\\')) => value.push(c),
+                        Some(c) => {
+                            value.push('\\');
+                            value.push(c);
+                        }
+                        None => break,
+                    },
+                    Some(c) => value.push(c),
+                    None => return Err(format!("line {}: unterminated double quote", start_line)),
+                }
+            },
+            '\\' => match chars.next() {
+                Some('\n') | None => {}
+                Some(c) => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_dotenv_plain() {
+        let content = "# comment\n\nA=1\n  B = two words  \nC=\n";
+        assert_eq!(
+            parse_dotenv(content).unwrap(),
+            pairs(&[("A", "1"), ("B", "two words"), ("C", "")])
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv_quoted() {
+        let content = "S='$HOME \\n'\nD=\"a\\\"b\\\\c\\nd\\$e\"\n";
+        assert_eq!(
+            parse_dotenv(content).unwrap(),
+            pairs(&[("S", "$HOME \\n"), ("D", "a\"b\\c\nd$e")])
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv_export_and_comments() {
+        let content = "export A=1\nexport  B=2 # note\nC=a#b c # d\n\
+                       D='x # y' # z\nE=\"p # q\"\t# r\nexported=3\nF= # empty\n";
+        assert_eq!(
+            parse_dotenv(content).unwrap(),
+            pairs(&[
+                ("A", "1"),
+                ("B", "2"),
+                ("C", "a#b c"),
+                ("D", "x # y"),
+                ("E", "p # q"),
+                ("exported", "3"),
+                ("F", ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv_errors() {
+        assert_eq!(parse_dotenv("A=1\nB").unwrap_err(), "line 2: expected NAME=value");
+        assert_eq!(
+            parse_dotenv("1A=x").unwrap_err(),
+            "line 1: invalid variable name '1A'"
+        );
+        assert_eq!(
+            parse_dotenv("A=\"open").unwrap_err(),
+            "line 1: unterminated double quote"
+        );
+        assert_eq!(
+            parse_dotenv("A='open").unwrap_err(),
+            "line 1: unterminated single quote"
+        );
+        assert_eq!(
+            parse_dotenv("A=\"x\" y").unwrap_err(),
+            "line 1: unexpected characters after closing quote"
+        );
+        assert_eq!(
+            parse_dotenv("A='x'y").unwrap_err(),
+            "line 1: unexpected characters after closing quote"
+        );
+    }
+
+    fn sources() -> Vec<(String, Assignments)> {
+        vec![
+            ("a.env".to_string(), pairs(&[("HOST", "a"), ("PORT", "1"), ("PORT", "2")])),
+            ("b.env".to_string(), pairs(&[("HOST", "b"), ("USER", "bob")])),
+        ]
+    }
+
+    fn environment(name: &str) -> Option<String> {
+        match name {
+            "USER" => Some("root".to_string()),
+            "PORT" => Some("2".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_merge_last_and_first() {
+        let (vars, warnings) = merge(sources(), environment, OnConflict::Last).unwrap();
+        assert_eq!(vars, pairs(&[("HOST", "b"), ("PORT", "2"), ("USER", "bob")]));
+        assert!(warnings.is_empty());
+        let (vars, _) = merge(sources(), environment, OnConflict::First).unwrap();
+        assert_eq!(vars, pairs(&[("HOST", "a"), ("PORT", "2")]));
+    }
+
+    #[test]
+    fn test_merge_warn_and_error() {
+        let (vars, warnings) = merge(sources(), environment, OnConflict::Warn).unwrap();
+        assert_eq!(vars, pairs(&[("HOST", "b"), ("PORT", "2"), ("USER", "bob")]));
+        assert_eq!(
+            warnings,
+            vec![
+                "variable 'HOST' from b.env overrides a.env",
+                "variable 'USER' from b.env overrides the environment",
+            ]
+        );
+        assert_eq!(
+            merge(sources(), environment, OnConflict::Error).unwrap_err(),
+            "variable 'HOST' has different values in a.env and b.env"
+        );
+        let (vars, _) = merge(sources()[..1].to_vec(), environment, OnConflict::Error).unwrap();
+        assert_eq!(vars, pairs(&[("HOST", "a"), ("PORT", "2")]));
+    }
+
+    #[test]
+    fn test_parse_systemd_comments_and_whitespace() {
+        let content = "# comment\n; also a comment\n\n  A = 1  \nB=two words \t\nC=\n";
+        assert_eq!(
+            parse_systemd(content).unwrap(),
+            pairs(&[("A", "1"), ("B", "two words"), ("C", "")])
+        );
+    }
+
+    #[test]
+    fn test_parse_systemd_quotes() {
+        let content = "S='$HOME \\n'\nD=\"a\\\"b\\\\c\\nd\\$e\"\nM='multi\nline'\nJ=\"x\" 'y' z\n";
+        assert_eq!(
+            parse_systemd(content).unwrap(),
+            pairs(&[
+                ("S", "$HOME \\n"),
+                ("D", "a\"b\\c\\nd$e"),
+                ("M", "multi\nline"),
+                ("J", "xyz"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_systemd_continuations() {
+        let content = "A=one \\\ntwo\nB=\"x\\\ny\"\n# comment \\\nC=hidden\nD=\\ lead\n";
+        assert_eq!(
+            parse_systemd(content).unwrap(),
+            pairs(&[("A", "one two"), ("B", "xy"), ("D", " lead")])
+        );
+    }
+
+    #[test]
+    fn test_parse_systemd_unterminated_quote_at_end() {
+        assert_eq!(parse_systemd("A='open").unwrap(), pairs(&[("A", "open")]));
+    }
+
+    #[test]
+    fn test_parse_systemd_errors() {
+        assert_eq!(parse_systemd("A=1\nB\n").unwrap_err(), "line 2: expected NAME=value");
+        assert_eq!(
+            parse_systemd("1A=x").unwrap_err(),
+            "line 1: invalid variable name '1A'"
+        );
+    }
+
+    #[test]
+    fn test_parse_shell() {
+        let content = "#!/bin/sh\n# settings\nexport A=1\nB='it'\\''s'  # note\n\
+                       export C=\"x \\\"y\\\" \\q $HOME\"; \nD=a\\ b\nE=\n";
+        assert_eq!(
+            parse_shell(content).unwrap(),
+            pairs(&[
+                ("A", "1"),
+                ("B", "it's"),
+                ("C", "x \"y\" \\q $HOME"),
+                ("D", "a b"),
+                ("E", ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_shell_multiline_quotes() {
+        let content = "A='one\ntwo'\nB=\"three\\\nfour\"\n";
+        assert_eq!(
+            parse_shell(content).unwrap(),
+            pairs(&[("A", "one\ntwo"), ("B", "threefour")])
+        );
+    }
+
+    #[test]
+    fn test_parse_shell_errors() {
+        assert_eq!(
+            parse_shell("A=1\nsource other.sh\n").unwrap_err(),
+            "line 2: expected NAME=value or export NAME=value"
+        );
+        assert_eq!(
+            parse_shell("export A\n").unwrap_err(),
+            "line 1: expected NAME=value or export NAME=value"
+        );
+        assert_eq!(
+            parse_shell("A=1 B=2\n").unwrap_err(),
+            "line 1: unexpected characters after value"
+        );
+        assert_eq!(
+            parse_shell("\nA='open\n").unwrap_err(),
+            "line 2: unterminated single quote"
+        );
+    }
+}
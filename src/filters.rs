@@ -0,0 +1,238 @@
+//! Filters usable in braced references, e.g. `${REPLICAS|int}`
+//!
+//! The validation filters pass the value through unchanged, but fail the
+//! render with a descriptive error if it does not conform. With the `json`
+//! feature, `jq:PATH` extracts a value from a JSON document. With the `wasm`
+//! feature, further filters can be loaded from WebAssembly plugins.
+
+#[cfg(feature = "wasm")]
+use crate::plugin::Plugin;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// The filters available to a render: the built-in ones and any loaded plugins
+#[derive(Default)]
+pub struct Filters {
+    #[cfg(feature = "wasm")]
+    plugins: Vec<Plugin>,
+    /// Variables whose values are masked in validation errors
+    secrets: HashSet<String>,
+}
+
+impl Filters {
+    /// Mask the values of the named variables in validation errors
+    pub fn with_secrets(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.secrets.extend(names);
+        self
+    }
+
+    /// Make the filters exported by a WebAssembly module available
+    #[cfg(feature = "wasm")]
+    pub fn load_plugin(&mut self, path: &Path) -> Result<(), String> {
+        self.plugins.push(Plugin::load(path)?);
+        Ok(())
+    }
+
+    /// Plugins need the wasm feature; without it loading one is an error
+    #[cfg(not(feature = "wasm"))]
+    pub fn load_plugin(&mut self, path: &Path) -> Result<(), String> {
+        Err(format!(
+            "{}: plugins are not supported, envsubst was built without the wasm feature",
+            path.display()
+        ))
+    }
+
+    /// Apply the named filter to the value of variable `var_name`
+    /// Built-in filters take precedence over plugin filters of the same name
+    pub fn apply(&self, filter: &str, var_name: &str, value: String) -> Result<String, String> {
+        let secret = self.secrets.contains(var_name);
+        if let Some(result) = apply_builtin(filter, var_name, value.clone(), secret) {
+            return result;
+        }
+        #[cfg(feature = "wasm")]
+        for plugin in &self.plugins {
+            if let Some(result) = plugin.call(filter, &value) {
+                return result.map_err(|msg| {
+                    format!("filter '{}' failed on variable '{}': {}", filter, var_name, msg)
+                });
+            }
+        }
+        Err(format!(
+            "unknown filter '{}' applied to variable '{}'",
+            filter, var_name
+        ))
+    }
+}
+
+/// Apply a built-in filter, or return None if there is no such built-in filter
+/// A `secret` value is masked in the error if it fails validation
+fn apply_builtin(
+    filter: &str,
+    var_name: &str,
+    value: String,
+    secret: bool,
+) -> Option<Result<String, String>> {
+    if let Some(path) = filter.strip_prefix("jq:") {
+        return Some(apply_jq(path, &value).map_err(|msg| {
+            format!("filter '{}' failed on variable '{}': {}", filter, var_name, msg)
+        }));
+    }
+    let valid = match filter {
+        "int" => is_int(&value),
+        "bool" => is_bool(&value),
+        "port" => is_port(&value),
+        "url" => is_url(&value),
+        _ => return None,
+    };
+    Some(if valid {
+        Ok(value)
+    } else {
+        Err(format!(
+            "variable '{}' has {} which is not a valid {}",
+            var_name,
+            describe_value(&value, secret),
+            filter
+        ))
+    })
+}
+
+/// Describe a value for an error message, showing only the length of a secret
+pub fn describe_value(value: &str, secret: bool) -> String {
+    if secret {
+        format!("a secret value of {} bytes", value.len())
+    } else {
+        format!("value '{}'", value)
+    }
+}
+
+/// Extract the value at a path from a JSON document
+#[cfg(feature = "json")]
+fn apply_jq(path: &str, value: &str) -> Result<String, String> {
+    crate::jq::extract(value, path)
+}
+
+/// The jq filter needs the json feature; without it using one is an error
+#[cfg(not(feature = "json"))]
+fn apply_jq(_path: &str, _value: &str) -> Result<String, String> {
+    Err("JSON support is not available, envsubst was built without the json feature".to_string())
+}
+
+/// Check for an optionally signed decimal integer
+fn is_int(value: &str) -> bool {
+    value.parse::<i64>().is_ok()
+}
+
+/// Check for one of the common boolean spellings
+fn is_bool(value: &str) -> bool {
+    matches!(
+        value.to_ascii_lowercase().as_str(),
+        "true" | "false" | "1" | "0" | "yes" | "no" | "on" | "off"
+    )
+}
+
+/// Check for a TCP/UDP port number (1-65535)
+fn is_port(value: &str) -> bool {
+    value.parse::<u16>().is_ok_and(|port| port != 0)
+}
+
+/// Check for an absolute URL of the form scheme://rest
+fn is_url(value: &str) -> bool {
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return false;
+    };
+    let mut scheme_chars = scheme.chars();
+    scheme_chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && scheme_chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        && !rest.is_empty()
+        && !rest.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int() {
+        assert!(is_int("42"));
+        assert!(is_int("-7"));
+        assert!(!is_int(""));
+        assert!(!is_int("4.2"));
+        assert!(!is_int("three"));
+    }
+
+    #[test]
+    fn test_bool() {
+        assert!(is_bool("true"));
+        assert!(is_bool("FALSE"));
+        assert!(is_bool("yes"));
+        assert!(is_bool("0"));
+        assert!(!is_bool("maybe"));
+        assert!(!is_bool(""));
+    }
+
+    #[test]
+    fn test_port() {
+        assert!(is_port("80"));
+        assert!(is_port("65535"));
+        assert!(!is_port("0"));
+        assert!(!is_port("65536"));
+        assert!(!is_port("http"));
+    }
+
+    #[test]
+    fn test_url() {
+        assert!(is_url("https://example.com/path"));
+        assert!(is_url("postgres+ssl://db:5432"));
+        assert!(!is_url("example.com"));
+        assert!(!is_url("https://"));
+        assert!(!is_url("1http://example.com"));
+        assert!(!is_url("https://exa mple.com"));
+    }
+
+    #[test]
+    fn test_apply_passes_value_through() {
+        let filters = Filters::default();
+        assert_eq!(filters.apply("int", "N", "5".to_string()).unwrap(), "5");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_apply_jq() {
+        let filters = Filters::default();
+        let config = r#"{"database": {"host": "db.internal"}}"#.to_string();
+        let host = filters.apply("jq:.database.host", "APP_CONFIG", config.clone());
+        assert_eq!(host.unwrap(), "db.internal");
+        let err = filters.apply("jq:.cache", "APP_CONFIG", config).unwrap_err();
+        assert!(err.starts_with("filter 'jq:.cache' failed on variable 'APP_CONFIG'"));
+    }
+
+    #[cfg(not(feature = "json"))]
+    #[test]
+    fn test_apply_jq_without_json_feature() {
+        let filters = Filters::default();
+        let err = filters.apply("jq:.a", "APP_CONFIG", "{}".to_string()).unwrap_err();
+        assert!(err.contains("without the json feature"));
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let filters = Filters::default();
+        let err = filters.apply("int", "REPLICAS", "many".to_string()).unwrap_err();
+        assert!(err.contains("REPLICAS") && err.contains("many") && err.contains("int"));
+        let err = filters.apply("nope", "REPLICAS", "1".to_string()).unwrap_err();
+        assert!(err.contains("unknown filter 'nope'"));
+    }
+
+    #[test]
+    fn test_apply_masks_secrets() {
+        let filters = Filters::default().with_secrets(["DB_PASS".to_string()]);
+        assert_eq!(
+            filters.apply("int", "DB_PASS", "hunter2".to_string()).unwrap_err(),
+            "variable 'DB_PASS' has a secret value of 7 bytes which is not a valid int"
+        );
+        assert_eq!(
+            filters.apply("port", "DB_PORT", "x".to_string()).unwrap_err(),
+            "variable 'DB_PORT' has value 'x' which is not a valid port"
+        );
+    }
+}
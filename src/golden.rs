@@ -0,0 +1,253 @@
+//! The test subcommand: golden-file tests for templates
+
+use crate::env_file;
+use crate::resolver::Resolver;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A discovered test case directory
+#[derive(Debug)]
+struct Case {
+    name: String,
+    dir: PathBuf,
+}
+
+/// Run every case under `dir`, printing one line per case, a diff for each
+/// failure and a summary. Returns whether all cases passed.
+///
+/// `resolver` creates the resolver for a case from the values in its env file.
+pub fn run<R, F>(dir: &Path, resolver: R, render: F) -> Result<bool, String>
+where
+    R: Fn(HashMap<String, String>) -> Resolver,
+    F: Fn(&str, &mut Resolver) -> Result<String, String>,
+{
+    let cases = discover(dir)?;
+    if cases.is_empty() {
+        return Err(format!("{}: no test cases found", dir.display()));
+    }
+
+    let mut failed = 0;
+    for case in &cases {
+        match run_case(case, &resolver, &render) {
+            Ok(()) => println!("ok   {}", case.name),
+            Err(report) => {
+                failed += 1;
+                println!("FAIL {}", case.name);
+                for line in report.lines() {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+    println!("{} passed, {} failed", cases.len() - failed, failed);
+    Ok(failed == 0)
+}
+
+/// Find the subdirectories of `dir` that contain a template and an expected output
+fn discover(dir: &Path) -> Result<Vec<Case>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut cases = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .path();
+        if path.join("template").is_file() && path.join("expected").is_file() {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            cases.push(Case { name, dir: path });
+        }
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+/// Render a single case; on failure return a report of what went wrong
+fn run_case<R, F>(case: &Case, resolver: &R, render: &F) -> Result<(), String>
+where
+    R: Fn(HashMap<String, String>) -> Resolver,
+    F: Fn(&str, &mut Resolver) -> Result<String, String>,
+{
+    let read = |file: &str| {
+        let path = case.dir.join(file);
+        fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    };
+
+    let template = read("template")?;
+    let expected = read("expected")?;
+    let values: HashMap<String, String> = if case.dir.join("env").exists() {
+        env_file::parse_dotenv(&read("env")?)
+            .map_err(|msg| format!("{}: {}", case.dir.join("env").display(), msg))?
+            .into_iter()
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let actual = render(&template, &mut resolver(values))?;
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(diff(&expected, &actual))
+    }
+}
+
+/// Largest LCS table built for a diff, in cells
+const MAX_DIFF_CELLS: usize = 1 << 20;
+
+/// Line diff of expected against actual output, '-' marking expected-only
+/// lines and '+' marking actual-only lines
+///
+/// Lines common to the start and end of both are matched directly. If what
+/// lies between is too large to diff in [`MAX_DIFF_CELLS`], only its first
+/// lines are shown, followed by a count of the rest.
+fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.split('\n').collect();
+    let new: Vec<&str> = actual.split('\n').collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut result = String::new();
+    for line in &old[..prefix] {
+        result.push_str(&format!(" {}\n", line));
+    }
+    let cells = (old_mid.len() + 1).saturating_mul(new_mid.len() + 1);
+    // With one side empty the table is a single row or column
+    if cells <= MAX_DIFF_CELLS || old_mid.is_empty() || new_mid.is_empty() {
+        diff_lines(old_mid, new_mid, &mut result);
+    } else {
+        result.push_str(&format!("-{}\n+{}\n", old_mid[0], new_mid[0]));
+        result.push_str(&format!(
+            "... {} more expected and {} more actual lines differ\n",
+            old_mid.len() - 1,
+            new_mid.len() - 1
+        ));
+    }
+    for line in &old[old.len() - suffix..] {
+        result.push_str(&format!(" {}\n", line));
+    }
+    result
+}
+
+/// Append the line diff of `old` against `new` to `result`, built from the
+/// longest common subsequence of the two
+fn diff_lines(old: &[&str], new: &[&str], result: &mut String) {
+    // Longest common subsequence lengths of every pair of suffixes
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            result.push_str(&format!(" {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            result.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            result.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn substitute(template: &str, resolver: &mut Resolver) -> Result<String, String> {
+        Ok(template.replace("$NAME", &resolver.lookup("NAME")?))
+    }
+
+    fn isolated(values: HashMap<String, String>) -> Resolver {
+        Resolver::from_values(values, true)
+    }
+
+    fn write_case(root: &Path, name: &str, template: &str, env: &str, expected: &str) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("template"), template).unwrap();
+        fs::write(dir.join("env"), env).unwrap();
+        fs::write(dir.join("expected"), expected).unwrap();
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\nc", "a\nx\nc"), " a\n-b\n+x\n c\n");
+        assert_eq!(diff("a", "a\nb"), " a\n+b\n");
+    }
+
+    #[test]
+    fn test_diff_large() {
+        let expected: Vec<String> = (0..3000).map(|n| format!("line {}", n)).collect();
+        let mut actual = expected.clone();
+        actual[1000] = "changed".to_string();
+        actual[1500] = "changed".to_string();
+        let result = diff(&expected.join("\n"), &actual.join("\n"));
+        assert!(result.starts_with(" line 0\n"));
+        assert!(result.contains("\n line 999\n-line 1000\n+changed\n line 1001\n"));
+        assert!(result.ends_with(" line 2999\n"));
+
+        let actual: Vec<String> = (0..3000).map(|n| format!("other {}", n)).collect();
+        let result = diff(&expected.join("\n"), &actual.join("\n"));
+        assert_eq!(
+            result,
+            "-line 0\n+other 0\n... 2999 more expected and 2999 more actual lines differ\n"
+        );
+    }
+
+    #[test]
+    fn test_run_cases() {
+        let root = std::env::temp_dir().join(format!("envsubst-golden-{}", std::process::id()));
+        write_case(&root, "pass", "hi $NAME\n", "NAME=bob\n", "hi bob\n");
+        write_case(&root, "fail", "hi $NAME\n", "NAME=eve\n", "hi bob\n");
+        fs::create_dir_all(root.join("not-a-case")).unwrap();
+
+        let cases = discover(&root).unwrap();
+        let names: Vec<&str> = cases.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["fail", "pass"]);
+
+        assert!(run_case(&cases[1], &isolated, &substitute).is_ok());
+        assert_eq!(
+            run_case(&cases[0], &isolated, &substitute).unwrap_err(),
+            "-hi bob\n+hi eve\n \n"
+        );
+        assert!(!run(&root, isolated, substitute).unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_run_cases_with_configured_resolver() {
+        let root = std::env::temp_dir().join(format!("envsubst-golden-map-{}", std::process::id()));
+        write_case(&root, "mapped", "hi $NAME\n", "APP_NAME=bob\n", "hi bob\n");
+
+        let mapped = |values| {
+            isolated(values).with_mapping([("NAME".to_string(), "APP_NAME".to_string())])
+        };
+        assert!(!run(&root, isolated, substitute).unwrap());
+        assert!(run(&root, mapped, substitute).unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
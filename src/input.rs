@@ -0,0 +1,245 @@
+//! Reading templates, optionally memory-mapping regular files
+//!
+//! Gzip-compressed input is recognized by its magic bytes and decompressed.
+//! With the remote feature, http(s) URLs are fetched in place of files.
+
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The text of a template, either read into memory or mapped from its file
+pub enum Input {
+    Read(String),
+    Mapped(Mmap),
+}
+
+impl Input {
+    /// The template text; a mapped file is checked to be valid UTF-8 in place
+    pub fn as_str(&self) -> Result<&str, String> {
+        match self {
+            Input::Read(text) => Ok(text),
+            Input::Mapped(map) => {
+                std::str::from_utf8(map).map_err(|_| "input is not valid UTF-8".to_string())
+            }
+        }
+    }
+}
+
+/// Read the template from stdin, mapping it instead if `mmap` is set and
+/// stdin is a regular file
+pub fn read_stdin(mmap: bool) -> Result<Input, String> {
+    #[cfg(unix)]
+    if mmap {
+        use std::os::fd::AsFd;
+        let file = io::stdin()
+            .as_fd()
+            .try_clone_to_owned()
+            .map(File::from)
+            .map_err(|e| format!("stdin: {}", e))?;
+        if let Some(map) = map_regular_file(&file).map_err(|e| format!("stdin: {}", e))? {
+            return from_map(map).map_err(|msg| format!("stdin: {}", msg));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = mmap;
+
+    let mut bytes = Vec::new();
+    io::stdin()
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("stdin: {}", e))?;
+    decode(bytes, None)
+        .map(Input::Read)
+        .map_err(|msg| format!("stdin: {}", msg))
+}
+
+/// Read the template from a file, mapping it instead if `mmap` is set and
+/// the path names a regular file
+pub fn read_file(path: &Path, mmap: bool) -> Result<Input, String> {
+    let error = |e: io::Error| format!("{}: {}", path.display(), e);
+    let mut file = File::open(path).map_err(error)?;
+    if mmap && let Some(map) = map_regular_file(&file).map_err(error)? {
+        return from_map(map).map_err(|msg| format!("{}: {}", path.display(), msg));
+    }
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).map_err(error)?;
+    decode(bytes, None)
+        .map(Input::Read)
+        .map_err(|msg| format!("{}: {}", path.display(), msg))
+}
+
+/// Read the template from a file, or fetch it if `source` is an http(s) URL
+pub fn read_source(source: &str, mmap: bool) -> Result<Input, String> {
+    if is_url(source) {
+        fetch(source)
+    } else {
+        read_file(Path::new(source), mmap)
+    }
+}
+
+/// Check whether an input source names an http(s) URL rather than a file
+fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+#[cfg(feature = "remote")]
+fn fetch(url: &str) -> Result<Input, String> {
+    let bytes = crate::remote::fetch(url)?;
+    decode(bytes, Some(crate::remote::MAX_SIZE))
+        .map(Input::Read)
+        .map_err(|msg| format!("{}: {}", url, msg))
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch(url: &str) -> Result<Input, String> {
+    Err(format!("{}: URL input requires the remote feature", url))
+}
+
+/// Use a mapped file as input, unless it is compressed and must be decompressed
+fn from_map(map: Mmap) -> Result<Input, String> {
+    if is_gzip(&map) {
+        decompress(&map, None).map(Input::Read)
+    } else {
+        Ok(Input::Mapped(map))
+    }
+}
+
+/// Turn raw input into text, decompressing it first if it is gzip data
+/// With `max_size`, decompressed text larger than that many bytes is rejected
+fn decode(bytes: Vec<u8>, max_size: Option<u64>) -> Result<String, String> {
+    if is_gzip(&bytes) {
+        decompress(&bytes, max_size)
+    } else {
+        String::from_utf8(bytes).map_err(|_| "input is not valid UTF-8".to_string())
+    }
+}
+
+/// Check for the gzip magic bytes
+fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b])
+}
+
+/// Decompress gzip data (possibly several concatenated members) into text,
+/// failing if it expands to more than `max_size` bytes
+fn decompress(bytes: &[u8], max_size: Option<u64>) -> Result<String, String> {
+    let limit = max_size.map_or(u64::MAX, |max| max + 1);
+    let mut text = String::new();
+    MultiGzDecoder::new(bytes)
+        .take(limit)
+        .read_to_string(&mut text)
+        .map_err(|e| format!("invalid gzip input: {}", e))?;
+    if let Some(max) = max_size
+        && text.len() as u64 > max
+    {
+        return Err(format!("decompressed input is larger than {} bytes", max));
+    }
+    Ok(text)
+}
+
+/// Map a file if it is a regular, non-empty file
+fn map_regular_file(file: &File) -> io::Result<Option<Mmap>> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the map is only read. Like any tool reading a file, the result
+    // is unspecified if another process truncates or rewrites the file while
+    // it is being rendered.
+    unsafe { Mmap::map(file) }.map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_file(name: &str, content: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("envsubst-input-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_file_mapped() {
+        let path = temp_file("mapped", b"hello $USER");
+        let input = read_file(&path, true).unwrap();
+        assert!(matches!(input, Input::Mapped(_)));
+        assert_eq!(input.as_str().unwrap(), "hello $USER");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_unmapped() {
+        let path = temp_file("read", b"hello $USER");
+        let input = read_file(&path, false).unwrap();
+        assert!(matches!(input, Input::Read(_)));
+        assert_eq!(input.as_str().unwrap(), "hello $USER");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_empty_file_is_not_mapped() {
+        let path = temp_file("empty", b"");
+        let input = read_file(&path, true).unwrap();
+        assert!(matches!(input, Input::Read(_)));
+        assert_eq!(input.as_str().unwrap(), "");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mapped_invalid_utf8() {
+        let path = temp_file("invalid", b"\xff\xfe");
+        let input = read_file(&path, true).unwrap();
+        assert_eq!(input.as_str().unwrap_err(), "input is not valid UTF-8");
+        let err = read_file(&path, false).err().unwrap();
+        assert!(err.ends_with(": input is not valid UTF-8"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_read_gzip_file() {
+        let path = temp_file("gzip", &gzip(b"hello $USER"));
+        for mmap in [false, true] {
+            let input = read_file(&path, mmap).unwrap();
+            assert!(matches!(input, Input::Read(_)));
+            assert_eq!(input.as_str().unwrap(), "hello $USER");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://config.example.com/app.conf.tpl"));
+        assert!(is_url("http://localhost:8080/app.conf.tpl"));
+        assert!(!is_url("app.conf.tpl"));
+        assert!(!is_url("https:app.conf.tpl"));
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut members = gzip(b"one ");
+        members.extend(gzip(b"two"));
+        assert_eq!(decode(members, None).unwrap(), "one two");
+        assert_eq!(decode(b"plain".to_vec(), None).unwrap(), "plain");
+        assert!(decode(vec![0x1f, 0x8b, 0], None).unwrap_err().starts_with("invalid gzip input"));
+    }
+
+    #[test]
+    fn test_decode_limits_decompressed_size() {
+        let bomb = gzip(&[b'x'; 1000]);
+        assert_eq!(decode(bomb.clone(), Some(1000)).unwrap().len(), 1000);
+        assert_eq!(
+            decode(bomb, Some(999)).unwrap_err(),
+            "decompressed input is larger than 999 bytes"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! A small subset of jq paths for extracting values from JSON documents
+//!
+//! Supported paths are `.` (the whole document) and sequences of `.key`,
+//! `.["key"]` and `[index]` steps, where a negative index counts from the end
+//! of an array, e.g. `.servers[0].host` or `.["dotted.key"][-1]`.
+
+use serde_json::Value;
+
+/// One step of a path
+#[derive(Debug, PartialEq)]
+enum Step {
+    Key(String),
+    Index(i64),
+}
+
+/// Extract the value at `path` from the JSON document `json`
+/// Strings are returned without quotes, other values as compact JSON
+pub fn extract(json: &str, path: &str) -> Result<String, String> {
+    let steps = parse_path(path)?;
+    let document: Value = serde_json::from_str(json).map_err(|e| format!("invalid JSON: {}", e))?;
+
+    let mut current = &document;
+    for step in &steps {
+        let next = match step {
+            Step::Key(key) => current.get(key.as_str()),
+            Step::Index(index) => current.as_array().and_then(|items| {
+                let index = if *index < 0 {
+                    items.len().checked_sub(index.unsigned_abs() as usize)?
+                } else {
+                    *index as usize
+                };
+                items.get(index)
+            }),
+        };
+        current = next.ok_or_else(|| format!("path '{}' not found", path))?;
+    }
+
+    Ok(match current {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    })
+}
+
+/// Parse a path into its steps
+fn parse_path(path: &str) -> Result<Vec<Step>, String> {
+    let invalid = || format!("invalid path '{}'", path);
+    let mut rest = path.strip_prefix('.').ok_or_else(invalid)?;
+    let mut steps = Vec::new();
+    // The leading '.' may be followed directly by a key
+    let mut after_dot = true;
+
+    while !rest.is_empty() {
+        if let Some(inner) = rest.strip_prefix('[') {
+            let end = find_closing_bracket(inner).ok_or_else(invalid)?;
+            let subscript = &inner[..end];
+            let step = if subscript.starts_with('"') {
+                Step::Key(serde_json::from_str(subscript).map_err(|_| invalid())?)
+            } else {
+                Step::Index(subscript.trim().parse().map_err(|_| invalid())?)
+            };
+            steps.push(step);
+            rest = &inner[end + 1..];
+            after_dot = false;
+        } else if after_dot {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(invalid());
+            }
+            steps.push(Step::Key(rest[..len].to_string()));
+            rest = &rest[len..];
+            after_dot = false;
+        } else if let Some(next) = rest.strip_prefix('.') {
+            rest = next;
+            after_dot = true;
+            if rest.is_empty() {
+                return Err(invalid());
+            }
+        } else {
+            return Err(invalid());
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Find the ']' closing a subscript, skipping over a quoted key
+fn find_closing_bracket(inner: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in inner.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ']' if !in_string => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "database": {"host": "db.internal", "port": 5432, "tls": true},
+        "servers": [{"name": "a"}, {"name": "b"}],
+        "dotted.key": [1, 2, 3],
+        "nothing": null
+    }"#;
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(parse_path(".").unwrap(), vec![]);
+        assert_eq!(
+            parse_path(".servers[0].name").unwrap(),
+            vec![
+                Step::Key("servers".to_string()),
+                Step::Index(0),
+                Step::Key("name".to_string())
+            ]
+        );
+        assert_eq!(
+            parse_path(r#".["a]\"b"][-1]"#).unwrap(),
+            vec![Step::Key("a]\"b".to_string()), Step::Index(-1)]
+        );
+        for bad in ["", "a", "..a", ".a.", ".a b", ".[", ".[x]", ".a[0]b"] {
+            assert!(parse_path(bad).is_err(), "{} should be invalid", bad);
+        }
+    }
+
+    #[test]
+    fn test_extract_scalars() {
+        assert_eq!(extract(CONFIG, ".database.host").unwrap(), "db.internal");
+        assert_eq!(extract(CONFIG, ".database.port").unwrap(), "5432");
+        assert_eq!(extract(CONFIG, ".database.tls").unwrap(), "true");
+        assert_eq!(extract(CONFIG, ".nothing").unwrap(), "null");
+    }
+
+    #[test]
+    fn test_extract_arrays_and_objects() {
+        assert_eq!(extract(CONFIG, ".servers[1].name").unwrap(), "b");
+        assert_eq!(extract(CONFIG, r#".["dotted.key"][-1]"#).unwrap(), "3");
+        assert_eq!(extract(CONFIG, ".servers[0]").unwrap(), r#"{"name":"a"}"#);
+        assert_eq!(extract("[1, 2]", ".").unwrap(), "[1,2]");
+    }
+
+    #[test]
+    fn test_extract_errors() {
+        assert_eq!(
+            extract(CONFIG, ".database.user").unwrap_err(),
+            "path '.database.user' not found"
+        );
+        assert!(extract(CONFIG, ".servers[2]").is_err());
+        assert!(extract(CONFIG, ".servers[-3]").is_err());
+        assert!(extract(CONFIG, ".database[0]").is_err());
+        assert!(extract("not json", ".").unwrap_err().starts_with("invalid JSON"));
+    }
+}
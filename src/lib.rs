@@ -0,0 +1,21 @@
+//! The template parser used by the envsubst command line tool
+//!
+//! [`Tokenizer`] splits a template into [`Event`]s: literal text and variable
+//! references. Consumers can render, rewrite or analyze templates on top of
+//! it and get exactly the references the tool itself would substitute.
+//!
+//! ```
+//! use envsubst::{Event, Tokenizer};
+//!
+//! let names: Vec<&str> = Tokenizer::new("$HOST:${PORT|int}")
+//!     .filter_map(|event| match event {
+//!         Event::Var { name, .. } => Some(name),
+//!         Event::Text(_) => None,
+//!     })
+//!     .collect();
+//! assert_eq!(names, ["HOST", "PORT"]);
+//! ```
+
+mod tokenizer;
+
+pub use tokenizer::{Event, Syntax, Tokenizer, is_valid_name};
@@ -1,81 +1,499 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use envsubst::{Event, Syntax, Tokenizer, is_valid_name};
+use flate2::write::GzEncoder;
+use regex::Regex;
 use std::collections::HashSet;
-use std::env;
-use std::io::{self, Read, Write};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+mod env_dump;
+mod env_file;
+mod filters;
+mod golden;
+mod input;
+mod parallel;
+#[cfg(feature = "json")]
+mod jq;
+#[cfg(feature = "wasm")]
+mod plugin;
+mod properties;
+#[cfg(feature = "remote")]
+mod remote;
+mod resolver;
+mod run;
+mod xml;
+
+use filters::Filters;
+use resolver::Resolver;
 
 #[derive(Parser)]
 #[command(name = "envsubst")]
 #[command(about = "Substitutes environment variables in shell format strings", long_about = None)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// List variables occurring in SHELL-FORMAT
     #[arg(long)]
     variables: bool,
 
+    /// Require VAR to match REGEX after resolution (may be repeated)
+    #[arg(long, value_name = "VAR=REGEX", value_parser = parse_requirement, global = true)]
+    require: Vec<Requirement>,
+
+    /// Treat VAR as secret: failed --require rules and filters show only the length of its value
+    #[arg(long, value_name = "VAR", global = true)]
+    secret: Vec<String>,
+
+    /// Fail on any '$' that does not form a valid variable reference
+    #[arg(long, global = true)]
+    strict_syntax: bool,
+
+    /// Syntax of the input; in structured formats only values are substituted
+    /// and substituted text is escaped as the format requires
+    #[arg(long, value_enum, default_value_t = TemplateFormat::Text)]
+    format: TemplateFormat,
+
+    /// Read the template from FILE instead of stdin; http(s) URLs are fetched
+    /// with the remote feature (30 second timeout, 64 MiB limit)
+    #[arg(short, long, value_name = "FILE|URL")]
+    input: Option<String>,
+
+    /// Memory-map the input instead of reading it when it is a regular file
+    #[arg(long, global = true)]
+    mmap: bool,
+
+    /// Compress the output; gzip input is always recognized and decompressed
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    compress: Compression,
+
+    /// Substitute large plain text inputs in up to N threads (0: one per CPU)
+    #[arg(short, long, value_name = "N", default_value_t = 1, global = true)]
+    jobs: usize,
+
+    /// Abort if the rendered output exceeds SIZE bytes (K, M and G suffixes allowed)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, global = true)]
+    max_output_size: Option<usize>,
+
+    /// Abort if a single substituted value exceeds SIZE bytes
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, global = true)]
+    max_value_size: Option<usize>,
+
+    /// Look up a variable again every time it is referenced instead of once per render
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Read variables from FILE, taking precedence over the environment (may be repeated);
+    /// with NAME=FILE they are only visible as ${NAME.VAR}, which needs --namespaces
+    #[arg(long, value_name = "[NAME=]FILE", value_parser = parse_env_file, global = true)]
+    env_file: Vec<EnvFile>,
+
+    /// Syntax of the --env-file files
+    #[arg(long, value_enum, default_value_t = env_file::Format::Dotenv, global = true)]
+    env_file_format: env_file::Format,
+
+    /// How to settle a variable given different values by the environment and
+    /// --env-file files, which take precedence in command line order
+    #[arg(long, value_enum, default_value_t = env_file::OnConflict::Last, global = true)]
+    on_conflict: env_file::OnConflict,
+
+    /// Look up ACTUAL whenever the template references NAME (may be repeated)
+    #[arg(long, value_name = "NAME=ACTUAL", value_parser = parse_mapping, global = true)]
+    map: Vec<(String, String)>,
+
+    /// Enable ${namespace.NAME} references selecting a source: env, dotenv
+    /// (--env-file FILE) or the NAME of an --env-file NAME=FILE
+    #[arg(long, global = true)]
+    namespaces: bool,
+
+    /// Load filters from a WebAssembly module (may be repeated; needs the wasm feature)
+    #[arg(long, value_name = "FILE", global = true)]
+    plugin: Vec<PathBuf>,
+
+    /// Reject templates using extensions to GNU envsubst syntax, such as filters
+    #[arg(long, conflicts_with = "namespaces", global = true)]
+    posix: bool,
+
     /// Shell format string specifying which variables to substitute
     /// If provided, only variables in this string will be substituted
     /// If not provided, all variables will be substituted
     shell_format: Option<String>,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Print the variables visible to a render, quoted for the chosen format
+    EnvDump {
+        /// Only include variables whose name starts with PREFIX
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = env_dump::Format::Dotenv)]
+        format: env_dump::Format,
+    },
+
+    /// Render a template and hand the result to a child process
+    Run {
+        /// Template file or http(s) URL to render
+        #[arg(short, long, value_name = "FILE|URL")]
+        input: String,
+
+        /// How the rendered output reaches the child
+        #[arg(long, value_enum, default_value_t = run::Via::Stdin)]
+        via: run::Via,
+
+        /// Command to run; with --via file, it needs an argument containing
+        /// "{}", which is replaced by the path of the rendered temporary file
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+
+    /// Render golden-file test cases and compare them with their expected output
+    ///
+    /// Every subdirectory of DIR containing a `template` and an `expected` file is
+    /// a case. The template is rendered using only the variables in the case's
+    /// optional `env` file (dotenv syntax), never the process environment.
+    Test {
+        /// Directory holding the test cases
+        dir: PathBuf,
+    },
+}
+
+/// Input syntaxes understood by --format
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum TemplateFormat {
+    /// Plain text: every reference is substituted as is
+    Text,
+    /// Java .properties: substitute in values, escaping backslashes,
+    /// line breaks and non-ASCII characters
+    Properties,
+    /// INI: substitute in values, leaving section headers and keys alone
+    Ini,
+    /// XML: substitute in text and attribute values, escaping markup characters
+    Xml,
+}
+
+/// Output compression selected with --compress
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Compression {
+    None,
+    Gzip,
+}
+
+/// Escapes a substituted value for the surrounding syntax
+type Escape = fn(&str) -> Result<String, String>;
+
+/// A part of the input and whether references in it are substituted
+/// The segments of an input, in order, concatenate to the whole input
+type Segment<'a> = (&'a str, bool);
+
+/// A validation rule given with --require
+#[derive(Clone, Debug)]
+struct Requirement {
+    name: String,
+    pattern: Regex,
+}
+
+/// Parse a VAR=REGEX argument into a Requirement
+fn parse_requirement(arg: &str) -> Result<Requirement, String> {
+    let (name, pattern) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected VAR=REGEX, got '{}'", arg))?;
+    if name.is_empty() {
+        return Err(format!("missing variable name in '{}'", arg));
+    }
+    let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+    Ok(Requirement {
+        name: name.to_string(),
+        pattern,
+    })
+}
+
+/// Parse a NAME=ACTUAL argument of --map
+fn parse_mapping(arg: &str) -> Result<(String, String), String> {
+    let (name, actual) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=ACTUAL, got '{}'", arg))?;
+    for name in [name, actual] {
+        if !is_valid_name(name) {
+            return Err(format!("invalid variable name '{}'", name));
+        }
+    }
+    Ok((name.to_string(), actual.to_string()))
+}
+
+/// A file given with --env-file, optionally bound to a namespace
+#[derive(Clone, Debug, PartialEq)]
+struct EnvFile {
+    namespace: Option<String>,
+    path: PathBuf,
+}
+
+/// Parse a FILE or NAME=FILE argument of --env-file
+/// A path containing '=' is only taken as NAME=FILE if NAME is a valid name
+fn parse_env_file(arg: &str) -> Result<EnvFile, String> {
+    match arg.split_once('=') {
+        Some((namespace, path)) if is_valid_name(namespace) => {
+            if matches!(namespace, "env" | "dotenv") {
+                return Err(format!("namespace '{}' is reserved", namespace));
+            }
+            if path.is_empty() {
+                return Err(format!("missing file in '{}'", arg));
+            }
+            Ok(EnvFile {
+                namespace: Some(namespace.to_string()),
+                path: PathBuf::from(path),
+            })
+        }
+        _ => Ok(EnvFile {
+            namespace: None,
+            path: PathBuf::from(arg),
+        }),
+    }
+}
+
+/// Parse a byte count with an optional K, M or G (binary) suffix
+fn parse_size(arg: &str) -> Result<usize, String> {
+    let (digits, multiplier) = match arg.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&arg[..arg.len() - 1], 1 << 10),
+        Some('M') => (&arg[..arg.len() - 1], 1 << 20),
+        Some('G') => (&arg[..arg.len() - 1], 1 << 30),
+        _ => (arg, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}'", arg))
+}
+
+/// Size caps applied while substituting
+#[derive(Clone, Copy, Debug, Default)]
+struct Limits {
+    max_output_size: Option<usize>,
+    max_value_size: Option<usize>,
+}
+
 fn main() {
     let cli = Cli::parse();
 
-    // Read input from stdin
-    let mut input = String::new();
-    io::stdin()
-        .read_to_string(&mut input)
-        .expect("Failed to read from stdin");
+    match &cli.command {
+        Some(Command::EnvDump { prefix, format }) => {
+            let file_vars = load_env_files(&cli).unwrap_or_else(|msg| exit_with_error(&msg));
+            let vars = env_dump::collect(prefix.as_deref(), file_vars);
+            print!("{}", env_dump::render(&vars, *format));
+            io::stdout().flush().unwrap();
+            return;
+        }
+        Some(Command::Run {
+            input,
+            via,
+            command,
+        }) => {
+            let template = input::read_source(input, cli.mmap)
+                .unwrap_or_else(|msg| exit_with_error(&msg));
+            let template = template.as_str().unwrap_or_else(|msg| exit_with_error(&msg));
+            let mut resolver = make_resolver(&cli).unwrap_or_else(|msg| exit_with_error(&msg));
+            let filters = make_filters(&cli).unwrap_or_else(|msg| exit_with_error(&msg));
+            let output = render(&cli, template, &mut resolver, &filters)
+                .unwrap_or_else(|msg| exit_with_error(&msg));
+            match run::run(&output, *via, command) {
+                Ok(code) => std::process::exit(code),
+                Err(msg) => exit_with_error(&msg),
+            }
+        }
+        Some(Command::Test { dir }) => {
+            let filters = make_filters(&cli).unwrap_or_else(|msg| exit_with_error(&msg));
+            let case_resolver = |values| {
+                configure_resolver(&cli, Resolver::from_values(values, !cli.no_cache))
+            };
+            let render_case = |template: &str, resolver: &mut Resolver| {
+                render(&cli, template, resolver, &filters)
+            };
+            match golden::run(dir, case_resolver, render_case) {
+                Ok(true) => std::process::exit(0),
+                Ok(false) => std::process::exit(1),
+                Err(msg) => exit_with_error(&msg),
+            }
+        }
+        None => {}
+    }
 
-    let allowed_vars = cli.shell_format
-        .as_ref()
-        .map(|sf| extract_variables(sf).into_iter().collect::<HashSet<String>>());
+    // Read input from --input, or stdin if not given
+    let input = match &cli.input {
+        Some(source) => input::read_source(source, cli.mmap),
+        None => input::read_stdin(cli.mmap),
+    };
+    let input = input.unwrap_or_else(|msg| exit_with_error(&msg));
+    let input = input.as_str().unwrap_or_else(|msg| exit_with_error(&msg));
 
     if cli.variables {
-        let source = cli.shell_format.as_ref().unwrap_or(&input);
+        let source = cli.shell_format.as_deref().unwrap_or(input);
         for var in extract_variables(source) {
             println!("{}", var);
         }
     } else {
-        print!("{}", substitute_variables(&input, allowed_vars.as_ref()));
-        io::stdout().flush().unwrap();
-    }
-}
-
-/// Parse a variable reference starting after the '$' character
-/// Returns (variable_name, is_braced) where is_braced indicates ${VAR} syntax
-fn parse_variable(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<(String, bool)> {
-    match chars.peek().copied()? {
-        '{' => {
-            chars.next(); // consume '{'
-            let var_name = consume_until(chars, '}');
-            Some((var_name, true))
-        }
-        ch if is_var_start(ch) => {
-            let var_name = consume_var_name(chars);
-            if !var_name.is_empty() {
-                Some((var_name, false))
-            } else {
-                None
-            }
+        let mut resolver = make_resolver(&cli).unwrap_or_else(|msg| exit_with_error(&msg));
+        let filters = make_filters(&cli).unwrap_or_else(|msg| exit_with_error(&msg));
+        match render(&cli, input, &mut resolver, &filters) {
+            Ok(output) => write_output(&output, cli.compress)
+                .unwrap_or_else(|e| exit_with_error(&format!("stdout: {}", e))),
+            Err(msg) => exit_with_error(&msg),
         }
-        _ => None,
     }
 }
 
-/// Extract all variable names from the input string
-fn extract_variables(input: &str) -> Vec<String> {
-    let mut vars = HashSet::new();
-    let mut chars = input.chars().peekable();
+/// Write the rendered output to stdout, compressing it if requested
+fn write_output(output: &str, compression: Compression) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    match compression {
+        Compression::None => stdout.write_all(output.as_bytes())?,
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(&mut stdout, flate2::Compression::default());
+            encoder.write_all(output.as_bytes())?;
+            encoder.finish()?;
+        }
+    }
+    stdout.flush()
+}
 
-    while let Some(ch) = chars.next() {
-        if ch != '$' {
+/// Print an error message prefixed with the program name and exit with status 1
+fn exit_with_error(msg: &str) -> ! {
+    eprintln!("envsubst: {}", msg);
+    std::process::exit(1);
+}
+
+/// Read the variables from all --env-file files not bound to a namespace,
+/// settling conflicts with each other and the environment per --on-conflict
+fn load_env_files(cli: &Cli) -> Result<Vec<(String, String)>, String> {
+    let mut sources = Vec::new();
+    for file in cli.env_file.iter().filter(|file| file.namespace.is_none()) {
+        let vars = env_file::load(&file.path, cli.env_file_format)?;
+        sources.push((file.path.display().to_string(), vars));
+    }
+    let (vars, warnings) =
+        env_file::merge(sources, |name| std::env::var(name).ok(), cli.on_conflict)?;
+    for warning in warnings {
+        eprintln!("envsubst: warning: {}", warning);
+    }
+    Ok(vars)
+}
+
+/// Create the resolver for a render from the command line
+fn make_resolver(cli: &Cli) -> Result<Resolver, String> {
+    let resolver = Resolver::new(!cli.no_cache).with_values(load_env_files(cli)?);
+    let mut resolver = configure_resolver(cli, resolver);
+    for file in &cli.env_file {
+        let Some(namespace) = &file.namespace else {
             continue;
+        };
+        if !cli.namespaces {
+            return Err(format!(
+                "--env-file {}={} needs --namespaces",
+                namespace,
+                file.path.display()
+            ));
         }
+        let vars = env_file::load(&file.path, cli.env_file_format)?;
+        resolver = resolver.with_namespace(namespace, vars);
+    }
+    Ok(resolver)
+}
 
-        if let Some((var_name, _)) = parse_variable(&mut chars) {
-            if !var_name.is_empty() {
-                vars.insert(var_name);
-            }
+/// Apply the --map rules and --namespaces to a resolver
+fn configure_resolver(cli: &Cli, resolver: Resolver) -> Resolver {
+    let resolver = resolver.with_mapping(cli.map.iter().cloned());
+    if cli.namespaces {
+        resolver.with_namespaces()
+    } else {
+        resolver
+    }
+}
+
+/// Load the filters available to templates, including any --plugin modules
+fn make_filters(cli: &Cli) -> Result<Filters, String> {
+    let mut filters = Filters::default().with_secrets(cli.secret.iter().cloned());
+    for path in &cli.plugin {
+        filters.load_plugin(path)?;
+    }
+    Ok(filters)
+}
+
+/// Check the --require rules and substitute the input according to the command line
+fn render(
+    cli: &Cli,
+    input: &str,
+    resolver: &mut Resolver,
+    filters: &Filters,
+) -> Result<String, String> {
+    let allowed_vars = cli.shell_format.as_ref().map(|sf| {
+        extract_variables(sf)
+            .into_iter()
+            .collect::<HashSet<String>>()
+    });
+    let (segments, escape): (Vec<Segment>, Escape) = match cli.format {
+        TemplateFormat::Text => (vec![(input, true)], no_escape),
+        TemplateFormat::Properties => (
+            properties::split_properties(input),
+            properties::escape_properties,
+        ),
+        TemplateFormat::Ini => (properties::split_ini(input), properties::escape_ini),
+        TemplateFormat::Xml => (xml::split_xml(input), xml::escape_xml),
+    };
+    if cli.posix {
+        check_posix(input, &segments)?;
+    }
+    if cli.strict_syntax {
+        check_syntax(input, &segments, cli.namespaces)?;
+    }
+    check_requirements(&cli.require, &cli.secret, resolver)?;
+    let limits = Limits {
+        max_output_size: cli.max_output_size,
+        max_value_size: cli.max_value_size,
+    };
+    if cli.format == TemplateFormat::Text && cli.jobs != 1 {
+        let jobs = match cli.jobs {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let chunks = parallel::split_chunks(input, jobs, parallel::MIN_CHUNK_SIZE);
+        return substitute_chunks(&chunks, allowed_vars.as_ref(), resolver, filters, &limits);
+    }
+
+    let mut result = String::new();
+    for (text, substitute) in segments {
+        if substitute {
+            substitute_into(
+                &mut result,
+                text,
+                allowed_vars.as_ref(),
+                resolver,
+                filters,
+                &limits,
+                escape,
+            )?;
+        } else {
+            result.push_str(text);
+            check_output_size(&result, &limits)?;
+        }
+    }
+    Ok(result)
+}
+
+/// Escape for plain text: substituted values are used as they are
+fn no_escape(value: &str) -> Result<String, String> {
+    Ok(value.to_string())
+}
+
+/// Extract all variable names from the input string
+fn extract_variables(input: &str) -> Vec<String> {
+    let mut vars = HashSet::new();
+    for event in Tokenizer::new(input) {
+        if let Event::Var { name, .. } = event
+            && !name.is_empty()
+        {
+            vars.insert(name.to_string());
         }
     }
 
@@ -84,85 +502,307 @@ fn extract_variables(input: &str) -> Vec<String> {
     result
 }
 
-/// Get the value to substitute for a variable name
-/// Returns Some(value) if substitution should happen (value may be empty if var not found)
-/// Returns None if the variable should not be substituted (keep original)
-fn get_substitution_value(var_name: &str, allowed_vars: Option<&HashSet<String>>) -> Option<String> {
-    let should_substitute = allowed_vars.map_or(true, |set| set.contains(var_name));
-    should_substitute.then(|| env::var(var_name).unwrap_or_default())
+/// Get the value to substitute for a variable reference
+/// Returns Ok(Some(value)) if substitution should happen (value may be empty if var not found)
+/// Returns Ok(None) if the variable should not be substituted (keep original)
+/// Returns Err if one of the reference's filters rejects the value
+fn get_substitution_value(
+    name: &str,
+    syntax: &Syntax,
+    allowed_vars: Option<&HashSet<String>>,
+    resolver: &mut Resolver,
+    filters: &Filters,
+) -> Result<Option<String>, String> {
+    if !allowed_vars.is_none_or(|set| set.contains(name)) {
+        return Ok(None);
+    }
+    let mut value = resolver.lookup(name)?;
+    for filter in syntax.filters() {
+        value = filters.apply(filter, name, value)?;
+    }
+    Ok(Some(value))
 }
 
-/// Reconstruct the original variable syntax
-fn reconstruct_variable(var_name: &str, is_braced: bool) -> String {
-    if is_braced {
-        format!("${{{}}}", var_name)
-    } else {
-        format!("${}", var_name)
+/// Check every --require rule against the resolved value of its variable
+/// An unset variable resolves to the empty string, as it would when substituted
+/// The values of `secrets` are masked in the error, leaving only their length
+fn check_requirements(
+    requirements: &[Requirement],
+    secrets: &[String],
+    resolver: &mut Resolver,
+) -> Result<(), String> {
+    for req in requirements {
+        let value = resolver.lookup(&req.name)?;
+        if !req.pattern.is_match(&value) {
+            return Err(format!(
+                "variable '{}' has {} which does not match '{}'",
+                req.name,
+                filters::describe_value(&value, secrets.contains(&req.name)),
+                req.pattern.as_str()
+            ));
+        }
     }
+    Ok(())
 }
 
-/// Substitute environment variables in the input string
-fn substitute_variables(input: &str, allowed_vars: Option<&HashSet<String>>) -> String {
-    let mut result = String::new();
-    let mut chars = input.chars().peekable();
+/// The events of the segments that are substituted, each with the byte offset
+/// where it starts in the whole input; reference spans are made relative to
+/// the whole input as well
+fn substituted_events<'a>(
+    segments: &[Segment<'a>],
+) -> impl Iterator<Item = (usize, Event<'a>)> {
+    segments
+        .iter()
+        .scan(0, |start, &(text, substitute)| {
+            let offset = *start;
+            *start += text.len();
+            Some((offset, text, substitute))
+        })
+        .filter(|&(_, _, substitute)| substitute)
+        .flat_map(|(start, text, _)| {
+            let mut offset = start;
+            Tokenizer::new(text).map(move |event| match event {
+                Event::Text(text) => {
+                    let at = offset;
+                    offset += text.len();
+                    (at, Event::Text(text))
+                }
+                Event::Var { name, syntax, span } => {
+                    let span = start + span.start..start + span.end;
+                    offset = span.end;
+                    (span.start, Event::Var { name, syntax, span })
+                }
+            })
+        })
+}
 
-    while let Some(ch) = chars.next() {
-        if ch != '$' {
-            result.push(ch);
+/// Check that every '$' in the substituted segments of the input starts a
+/// well-formed variable reference
+/// With `namespaces`, braced names may be of the form namespace.NAME
+/// Errors name the offending reference with its line and column (both 1-based)
+fn check_syntax(input: &str, segments: &[Segment], namespaces: bool) -> Result<(), String> {
+    for (offset, event) in substituted_events(segments) {
+        let (name, syntax, span) = match event {
+            Event::Text(text) => {
+                if let Some(stray) = text.find('$') {
+                    return Err(format!("stray '$' at {}", location(input, offset + stray)));
+                }
+                continue;
+            }
+            Event::Var { name, syntax, span } => (name, syntax, span),
+        };
+        if syntax == Syntax::Simple {
             continue;
         }
 
-        match parse_variable(&mut chars) {
-            Some((var_name, is_braced)) => {
-                let replacement = get_substitution_value(&var_name, allowed_vars)
-                    .unwrap_or_else(|| reconstruct_variable(&var_name, is_braced));
-                result.push_str(&replacement);
-            }
-            None => result.push(ch),
+        let reference = &input[span.clone()];
+        let Some(content) = reference.strip_prefix("${").and_then(|r| r.strip_suffix('}')) else {
+            return Err(format!("unclosed '${{' at {}", location(input, span.start)));
+        };
+        let name_part = match name.split_once('.') {
+            Some((namespace, var_name)) if namespaces && is_valid_name(namespace) => var_name,
+            _ => name,
+        };
+        if !is_valid_name(name_part) {
+            return Err(format!(
+                "invalid variable name '{}' in '{}' at {}",
+                name,
+                reference,
+                location(input, span.start)
+            ));
+        }
+        if syntax.filters().iter().any(|filter| filter.is_empty()) {
+            return Err(format!(
+                "empty filter in '${{{}}}' at {}",
+                content,
+                location(input, span.start)
+            ));
         }
     }
 
-    result
+    Ok(())
 }
 
-/// Check if a character can start a variable name (letter or underscore)
-fn is_var_start(ch: char) -> bool {
-    ch.is_ascii_alphabetic() || ch == '_'
+/// Check that the substituted segments of the input only use references GNU
+/// envsubst understands
+/// Filters (${VAR|filter}) and namespaced references (${ns.VAR}) are rejected, as
+/// are braced references GNU envsubst would leave as they are: unclosed ones
+/// and ones whose content is not a valid name
+fn check_posix(input: &str, segments: &[Segment]) -> Result<(), String> {
+    for (_, event) in substituted_events(segments) {
+        let Event::Var { name, syntax, span } = event else {
+            continue;
+        };
+        if syntax == Syntax::Simple {
+            continue;
+        }
+        let feature = if !input[span.clone()].ends_with('}') {
+            "unclosed references are"
+        } else if !syntax.filters().is_empty() {
+            "filters are"
+        } else if name.contains('.') {
+            "namespaced references are"
+        } else if !is_valid_name(name) {
+            "invalid variable names are"
+        } else {
+            continue;
+        };
+        return Err(format!(
+            "{} not supported with --posix: '{}' at {}",
+            feature,
+            &input[span.clone()],
+            location(input, span.start)
+        ));
+    }
+    Ok(())
+}
+
+/// Describe a byte offset in the input as a 1-based line and column
+fn location(input: &str, offset: usize) -> String {
+    let before = &input[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before[before.rfind('\n').map_or(0, |i| i + 1)..].chars().count() + 1;
+    format!("line {}, column {}", line, column)
 }
 
-/// Check if a character can be part of a variable name (letter, digit, or underscore)
-fn is_var_char(ch: char) -> bool {
-    ch.is_ascii_alphanumeric() || ch == '_'
+/// Substitute environment variables in the input string
+fn substitute_variables(
+    input: &str,
+    allowed_vars: Option<&HashSet<String>>,
+    resolver: &mut Resolver,
+    filters: &Filters,
+    limits: &Limits,
+    escape: Escape,
+) -> Result<String, String> {
+    let mut result = String::new();
+    substitute_into(&mut result, input, allowed_vars, resolver, filters, limits, escape)?;
+    Ok(result)
 }
 
-/// Consume characters until the delimiter is found
-fn consume_until(chars: &mut std::iter::Peekable<std::str::Chars>, delimiter: char) -> String {
+/// Substitute each chunk in its own thread and join the results in order
+/// Every thread works with its own copy of the resolver
+fn substitute_chunks(
+    chunks: &[&str],
+    allowed_vars: Option<&HashSet<String>>,
+    resolver: &Resolver,
+    filters: &Filters,
+    limits: &Limits,
+) -> Result<String, String> {
+    let outputs: Vec<Result<String, String>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .map(|chunk| {
+                let mut resolver = resolver.clone();
+                scope.spawn(move || {
+                    substitute_variables(chunk, allowed_vars, &mut resolver, filters, limits, no_escape)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("substitution thread panicked"))
+            .collect()
+    });
+
     let mut result = String::new();
-    while let Some(&ch) = chars.peek() {
-        if ch == delimiter {
-            chars.next(); // consume the delimiter
-            break;
+    for output in outputs {
+        result.push_str(&output?);
+        check_output_size(&result, limits)?;
+    }
+    Ok(result)
+}
+
+/// Substitute environment variables in the input string, appending to `result`
+/// The output size limit applies to all of `result`, not just the appended part
+fn substitute_into(
+    result: &mut String,
+    input: &str,
+    allowed_vars: Option<&HashSet<String>>,
+    resolver: &mut Resolver,
+    filters: &Filters,
+    limits: &Limits,
+    escape: Escape,
+) -> Result<(), String> {
+    for event in Tokenizer::new(input) {
+        match event {
+            Event::Text(text) => result.push_str(text),
+            Event::Var { name, syntax, span } => {
+                match get_substitution_value(name, &syntax, allowed_vars, resolver, filters)? {
+                    Some(value) => {
+                        check_value_size(name, &value, limits)?;
+                        let escaped = escape(&value).map_err(|msg| {
+                            format!("cannot substitute variable '{}': {}", name, msg)
+                        })?;
+                        result.push_str(&escaped);
+                    }
+                    None => result.push_str(&input[span]),
+                }
+            }
         }
-        result.push(chars.next().unwrap());
+
+        check_output_size(result, limits)?;
     }
-    result
+
+    Ok(())
 }
 
-/// Consume a variable name (alphanumeric and underscore)
-fn consume_var_name(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
-    let mut result = String::new();
-    while let Some(&ch) = chars.peek() {
-        if !is_var_char(ch) {
-            break;
+/// Check the rendered output against the total size limit
+fn check_output_size(result: &str, limits: &Limits) -> Result<(), String> {
+    match limits.max_output_size {
+        Some(max) if result.len() > max => {
+            Err(format!("rendered output exceeds the limit of {} bytes", max))
         }
-        result.push(chars.next().unwrap());
+        _ => Ok(()),
+    }
+}
+
+/// Check a substituted value against the per-value size limit
+fn check_value_size(var_name: &str, value: &str, limits: &Limits) -> Result<(), String> {
+    match limits.max_value_size {
+        Some(max) if value.len() > max => Err(format!(
+            "value of variable '{}' is {} bytes, exceeding the limit of {} bytes",
+            var_name,
+            value.len(),
+            max
+        )),
+        _ => Ok(()),
     }
-    result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
+
+    /// Check the syntax of a plain text template, which is substituted throughout
+    fn check_text_syntax(input: &str, namespaces: bool) -> Result<(), String> {
+        check_syntax(input, &[(input, true)], namespaces)
+    }
+
+    /// Check a plain text template for --posix
+    fn check_text_posix(input: &str) -> Result<(), String> {
+        check_posix(input, &[(input, true)])
+    }
+
+    /// Substitute using the environment, the built-in filters and no size limits
+    fn substitute(input: &str, allowed_vars: Option<&HashSet<String>>) -> Result<String, String> {
+        substitute_with_limits(input, allowed_vars, &Limits::default())
+    }
+
+    fn substitute_with_limits(
+        input: &str,
+        allowed_vars: Option<&HashSet<String>>,
+        limits: &Limits,
+    ) -> Result<String, String> {
+        substitute_variables(
+            input,
+            allowed_vars,
+            &mut Resolver::default(),
+            &Filters::default(),
+            limits,
+            no_escape,
+        )
+    }
 
     #[test]
     fn test_extract_variables_simple() {
@@ -212,7 +852,7 @@ mod tests {
             env::set_var("TEST_VAR", "test_value");
         }
         let input = "Value: $TEST_VAR";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "Value: test_value");
         unsafe {
             env::remove_var("TEST_VAR");
@@ -225,7 +865,7 @@ mod tests {
             env::set_var("TEST_VAR", "braced_value");
         }
         let input = "Value: ${TEST_VAR}";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "Value: braced_value");
         unsafe {
             env::remove_var("TEST_VAR");
@@ -238,7 +878,7 @@ mod tests {
             env::remove_var("UNDEFINED_VAR_12345");
         }
         let input = "Value: $UNDEFINED_VAR_12345";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "Value: ");
     }
 
@@ -249,7 +889,7 @@ mod tests {
             env::set_var("VAR2", "value2");
         }
         let input = "$VAR1 and ${VAR2}";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "value1 and value2");
         unsafe {
             env::remove_var("VAR1");
@@ -270,7 +910,7 @@ mod tests {
         allowed.insert("VAR3".to_string());
         
         let input = "$VAR1 $VAR2 $VAR3";
-        let result = substitute_variables(input, Some(&allowed));
+        let result = substitute(input, Some(&allowed)).unwrap();
         assert_eq!(result, "value1 $VAR2 value3");
         
         unsafe {
@@ -287,7 +927,7 @@ mod tests {
             env::set_var("B", "bar");
         }
         let input = "$A$B";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "foobar");
         unsafe {
             env::remove_var("A");
@@ -301,7 +941,7 @@ mod tests {
             env::set_var("NAME", "World");
         }
         let input = "Hello, $NAME!";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "Hello, World!");
         unsafe {
             env::remove_var("NAME");
@@ -311,43 +951,21 @@ mod tests {
     #[test]
     fn test_substitute_lone_dollar() {
         let input = "Price: $100";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "Price: $100");
     }
 
     #[test]
     fn test_substitute_dollar_at_end() {
         let input = "ends with $";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "ends with $");
     }
 
-    #[test]
-    fn test_is_var_start() {
-        assert!(is_var_start('a'));
-        assert!(is_var_start('Z'));
-        assert!(is_var_start('_'));
-        assert!(!is_var_start('1'));
-        assert!(!is_var_start('-'));
-        assert!(!is_var_start('$'));
-    }
-
-    #[test]
-    fn test_is_var_char() {
-        assert!(is_var_char('a'));
-        assert!(is_var_char('Z'));
-        assert!(is_var_char('_'));
-        assert!(is_var_char('0'));
-        assert!(is_var_char('9'));
-        assert!(!is_var_char('-'));
-        assert!(!is_var_char('$'));
-        assert!(!is_var_char(' '));
-    }
-
     #[test]
     fn test_empty_braces() {
         let input = "${}";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "");
     }
 
@@ -357,7 +975,7 @@ mod tests {
             env::set_var("VAR", "value");
         }
         let input = "${VAR";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         // Unclosed brace consumes rest of string as variable name
         assert_eq!(result, "value");
         unsafe {
@@ -371,7 +989,7 @@ mod tests {
             env::set_var("MY_VAR_123", "test");
         }
         let input = "$MY_VAR_123";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "test");
         unsafe {
             env::remove_var("MY_VAR_123");
@@ -384,10 +1002,397 @@ mod tests {
             env::set_var("VAR", "value");
         }
         let input = "$VAR-suffix";
-        let result = substitute_variables(input, None);
+        let result = substitute(input, None).unwrap();
         assert_eq!(result, "value-suffix");
         unsafe {
             env::remove_var("VAR");
         }
     }
+
+    #[test]
+    fn test_parse_requirement() {
+        let req = parse_requirement("PORT=^[0-9]+$").unwrap();
+        assert_eq!(req.name, "PORT");
+        assert_eq!(req.pattern.as_str(), "^[0-9]+$");
+        assert!(parse_requirement("PORT").is_err());
+        assert!(parse_requirement("=^x$").is_err());
+        assert!(parse_requirement("PORT=(").is_err());
+    }
+
+    #[test]
+    fn test_check_requirements() {
+        unsafe {
+            env::set_var("REQ_PORT", "8080");
+            env::set_var("REQ_HOST", "not a port");
+        }
+        let ok = parse_requirement("REQ_PORT=^[0-9]+$").unwrap();
+        assert!(check_requirements(std::slice::from_ref(&ok), &[], &mut Resolver::default()).is_ok());
+
+        let bad = parse_requirement("REQ_HOST=^[0-9]+$").unwrap();
+        let err = check_requirements(&[ok, bad], &[], &mut Resolver::default()).unwrap_err();
+        assert!(err.contains("REQ_HOST"));
+        assert!(err.contains("not a port"));
+        unsafe {
+            env::remove_var("REQ_PORT");
+            env::remove_var("REQ_HOST");
+        }
+    }
+
+    #[test]
+    fn test_check_requirements_masks_secrets() {
+        unsafe {
+            env::set_var("REQ_SECRET_PASS", "hunter2");
+        }
+        let req = parse_requirement("REQ_SECRET_PASS=^.{12,}$").unwrap();
+        let secrets = ["REQ_SECRET_PASS".to_string()];
+        assert_eq!(
+            check_requirements(&[req], &secrets, &mut Resolver::default()).unwrap_err(),
+            "variable 'REQ_SECRET_PASS' has a secret value of 7 bytes which does not match '^.{12,}$'"
+        );
+        unsafe {
+            env::remove_var("REQ_SECRET_PASS");
+        }
+    }
+
+    #[test]
+    fn test_extract_variables_with_filters() {
+        let input = "${REPLICAS|int} ${ENDPOINT|url}";
+        let vars = extract_variables(input);
+        assert_eq!(vars, vec!["ENDPOINT", "REPLICAS"]);
+    }
+
+    #[test]
+    fn test_substitute_with_filter() {
+        unsafe {
+            env::set_var("FILTER_REPLICAS", "3");
+        }
+        let result = substitute("replicas: ${FILTER_REPLICAS|int}", None).unwrap();
+        assert_eq!(result, "replicas: 3");
+        unsafe {
+            env::remove_var("FILTER_REPLICAS");
+        }
+    }
+
+    #[test]
+    fn test_substitute_with_failing_filter() {
+        unsafe {
+            env::set_var("FILTER_BAD_PORT", "http");
+        }
+        let err = substitute("${FILTER_BAD_PORT|port}", None).unwrap_err();
+        assert!(err.contains("FILTER_BAD_PORT"));
+        unsafe {
+            env::remove_var("FILTER_BAD_PORT");
+        }
+    }
+
+    #[test]
+    fn test_filtered_variable_not_in_shell_format_is_kept() {
+        let allowed = HashSet::new();
+        let result = substitute("${KEEP_ME|int}", Some(&allowed)).unwrap();
+        assert_eq!(result, "${KEEP_ME|int}");
+    }
+
+    #[test]
+    fn test_check_syntax_valid() {
+        assert!(check_text_syntax("plain text", false).is_ok());
+        assert!(check_text_syntax("$USER ${HOME} ${PORT|int|port} $_x1", false).is_ok());
+    }
+
+    #[test]
+    fn test_check_syntax_stray_dollar() {
+        assert_eq!(check_text_syntax("cost: $ 5", false).unwrap_err(), "stray '$' at line 1, column 7");
+        assert_eq!(check_text_syntax("a\n$1abc", false).unwrap_err(), "stray '$' at line 2, column 1");
+        assert_eq!(check_text_syntax("ends with $", false).unwrap_err(), "stray '$' at line 1, column 11");
+        assert_eq!(check_text_syntax("$ {VAR}", false).unwrap_err(), "stray '$' at line 1, column 1");
+    }
+
+    #[test]
+    fn test_check_syntax_bad_braces() {
+        assert_eq!(
+            check_text_syntax("x ${}", false).unwrap_err(),
+            "invalid variable name '' in '${}' at line 1, column 3"
+        );
+        assert_eq!(
+            check_text_syntax("${bad name}", false).unwrap_err(),
+            "invalid variable name 'bad name' in '${bad name}' at line 1, column 1"
+        );
+        assert_eq!(
+            check_text_syntax("${VAR|}", false).unwrap_err(),
+            "empty filter in '${VAR|}' at line 1, column 1"
+        );
+        assert_eq!(
+            check_text_syntax("\n  ${VAR", false).unwrap_err(),
+            "unclosed '${' at line 2, column 3"
+        );
+    }
+
+    #[test]
+    fn test_check_syntax_tracks_lines_inside_braces() {
+        assert_eq!(
+            check_text_syntax("${A}\n${B\n} $", false).unwrap_err(),
+            "invalid variable name 'B\n' in '${B\n}' at line 2, column 1"
+        );
+        assert_eq!(check_text_syntax("${A|int\n}\n$", false).unwrap_err(), "stray '$' at line 3, column 1");
+    }
+
+    #[test]
+    fn test_check_syntax_namespaces() {
+        assert!(check_text_syntax("${env.HOME}", false).is_err());
+        assert!(check_text_syntax("${env.HOME} ${dotenv.PORT|int}", true).is_ok());
+        assert!(check_text_syntax("${env.}", true).is_err());
+        assert!(check_text_syntax("${.HOME}", true).is_err());
+        assert!(check_text_syntax("${a.b.c}", true).is_err());
+    }
+
+    #[test]
+    fn test_check_text_posix() {
+        assert!(check_text_posix("$USER ${HOME} $ $1 cost$").is_ok());
+        assert_eq!(
+            check_text_posix("a\nport: ${PORT|int}").unwrap_err(),
+            "filters are not supported with --posix: '${PORT|int}' at line 2, column 7"
+        );
+        assert_eq!(
+            check_text_posix("${env.HOME}").unwrap_err(),
+            "namespaced references are not supported with --posix: '${env.HOME}' at line 1, column 1"
+        );
+    }
+
+    #[test]
+    fn test_check_posix_rejects_what_gnu_leaves_literal() {
+        assert_eq!(
+            check_text_posix("a=${bad name}").unwrap_err(),
+            "invalid variable names are not supported with --posix: '${bad name}' at line 1, column 3"
+        );
+        assert_eq!(
+            check_text_posix("c=${}").unwrap_err(),
+            "invalid variable names are not supported with --posix: '${}' at line 1, column 3"
+        );
+        assert_eq!(
+            check_text_posix("b=${VAR c").unwrap_err(),
+            "unclosed references are not supported with --posix: '${VAR c' at line 1, column 3"
+        );
+    }
+
+    #[test]
+    fn test_checks_skip_xml_markup() {
+        let input = "<!-- costs $5, ${A|int} -->\n<a>${A}<![CDATA[$ raw]]></a>";
+        let segments = xml::split_xml(input);
+        assert!(check_syntax(input, &segments, false).is_ok());
+        assert!(check_posix(input, &segments).is_ok());
+
+        let input = "<!-- costs $5 -->\n<a>${A}<![CDATA[$ raw]]> $</a>";
+        assert_eq!(
+            check_syntax(input, &xml::split_xml(input), false).unwrap_err(),
+            "stray '$' at line 2, column 26"
+        );
+    }
+
+    #[test]
+    fn test_checks_skip_properties_and_ini_outside_values() {
+        let input = "# price $5\nk=${X}\n";
+        assert!(check_syntax(input, &properties::split_properties(input), false).is_ok());
+        let input = "; price $5\n[s$]\nk = ${X}\n";
+        assert!(check_syntax(input, &properties::split_ini(input), false).is_ok());
+
+        let input = "# price $5\nkey=${X|} $\n";
+        assert_eq!(
+            check_syntax(input, &properties::split_properties(input), false).unwrap_err(),
+            "empty filter in '${X|}' at line 2, column 5"
+        );
+        assert_eq!(
+            check_posix(input, &properties::split_properties(input)).unwrap_err(),
+            "filters are not supported with --posix: '${X|}' at line 2, column 5"
+        );
+    }
+
+    #[test]
+    fn test_location() {
+        assert_eq!(location("abc", 0), "line 1, column 1");
+        assert_eq!(location("ab\ncd", 4), "line 2, column 2");
+        assert_eq!(location("é$", 2), "line 1, column 2");
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        assert_eq!(
+            parse_mapping("DB_HOST=APP_DB_HOST"),
+            Ok(("DB_HOST".to_string(), "APP_DB_HOST".to_string()))
+        );
+        assert!(parse_mapping("DB_HOST").is_err());
+        assert!(parse_mapping("=APP_DB_HOST").is_err());
+        assert!(parse_mapping("DB_HOST=bad name").is_err());
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        assert_eq!(
+            parse_env_file("secrets=/run/secrets.env"),
+            Ok(EnvFile {
+                namespace: Some("secrets".to_string()),
+                path: PathBuf::from("/run/secrets.env"),
+            })
+        );
+        assert_eq!(
+            parse_env_file("./a=b.env"),
+            Ok(EnvFile {
+                namespace: None,
+                path: PathBuf::from("./a=b.env"),
+            })
+        );
+        assert_eq!(
+            parse_env_file("env=x.env").unwrap_err(),
+            "namespace 'env' is reserved"
+        );
+        assert_eq!(parse_env_file("secrets=").unwrap_err(), "missing file in 'secrets='");
+    }
+
+    #[test]
+    fn test_make_resolver_with_named_env_file() {
+        let path = env::temp_dir().join(format!("envsubst-named-{}.env", std::process::id()));
+        std::fs::write(&path, "NAMED_ENV_PASS=hunter2\n").unwrap();
+        let arg = format!("secrets={}", path.display());
+        let cli = Cli::parse_from(["envsubst", "--namespaces", "--env-file", &arg]);
+        let mut resolver = make_resolver(&cli).unwrap();
+        assert_eq!(resolver.lookup("secrets.NAMED_ENV_PASS").unwrap(), "hunter2");
+        assert_eq!(resolver.lookup("NAMED_ENV_PASS").unwrap(), "");
+
+        let cli = Cli::parse_from(["envsubst", "--env-file", &arg]);
+        assert_eq!(
+            make_resolver(&cli).unwrap_err(),
+            format!("--env-file {} needs --namespaces", arg)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4k"), Ok(4096));
+        assert_eq!(parse_size("10M"), Ok(10 << 20));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("1T").is_err());
+    }
+
+    #[test]
+    fn test_max_output_size() {
+        let limits = Limits {
+            max_output_size: Some(5),
+            ..Limits::default()
+        };
+        assert_eq!(substitute_with_limits("12345", None, &limits).unwrap(), "12345");
+        let err = substitute_with_limits("123456", None, &limits).unwrap_err();
+        assert_eq!(err, "rendered output exceeds the limit of 5 bytes");
+    }
+
+    #[test]
+    fn test_max_output_size_counts_values() {
+        unsafe {
+            env::set_var("LIMIT_BIG", "0123456789");
+        }
+        let limits = Limits {
+            max_output_size: Some(8),
+            ..Limits::default()
+        };
+        assert!(substitute_with_limits("$LIMIT_BIG", None, &limits).is_err());
+        unsafe {
+            env::remove_var("LIMIT_BIG");
+        }
+    }
+
+    #[test]
+    fn test_max_value_size() {
+        unsafe {
+            env::set_var("LIMIT_VALUE", "0123456789");
+        }
+        let limits = Limits {
+            max_value_size: Some(4),
+            ..Limits::default()
+        };
+        let err = substitute_with_limits("$LIMIT_VALUE", None, &limits).unwrap_err();
+        assert_eq!(
+            err,
+            "value of variable 'LIMIT_VALUE' is 10 bytes, exceeding the limit of 4 bytes"
+        );
+        // Variables left untouched by a shell format are not values
+        let allowed = HashSet::new();
+        assert!(substitute_with_limits("$LIMIT_VALUE", Some(&allowed), &limits).is_ok());
+        unsafe {
+            env::remove_var("LIMIT_VALUE");
+        }
+    }
+
+    #[test]
+    fn test_render_masks_secret_in_filter_error() {
+        unsafe {
+            env::set_var("RENDER_SECRET_PASS", "hunter2");
+        }
+        let cli = Cli::parse_from(["envsubst", "--secret", "RENDER_SECRET_PASS"]);
+        let filters = make_filters(&cli).unwrap();
+        let err = render(&cli, "${RENDER_SECRET_PASS|int}", &mut Resolver::default(), &filters);
+        assert_eq!(
+            err.unwrap_err(),
+            "variable 'RENDER_SECRET_PASS' has a secret value of 7 bytes which is not a valid int"
+        );
+        unsafe {
+            env::remove_var("RENDER_SECRET_PASS");
+        }
+    }
+
+    #[test]
+    fn test_render_properties_format() {
+        unsafe {
+            env::set_var("FORMAT_PROP_DIR", "C:\\app");
+        }
+        let cli = Cli::parse_from(["envsubst", "--format", "properties"]);
+        let input = "# $FORMAT_PROP_DIR\n$FORMAT_PROP_DIR.dir = $FORMAT_PROP_DIR\n";
+        let output = render(&cli, input, &mut Resolver::default(), &Filters::default()).unwrap();
+        assert_eq!(output, "# $FORMAT_PROP_DIR\n$FORMAT_PROP_DIR.dir = C:\\\\app\n");
+
+        let cli = Cli::parse_from(["envsubst", "--format", "ini", "--max-output-size", "10"]);
+        let err = render(&cli, "[section]\nkey = value\n", &mut Resolver::default(), &Filters::default());
+        assert_eq!(err.unwrap_err(), "rendered output exceeds the limit of 10 bytes");
+        unsafe {
+            env::remove_var("FORMAT_PROP_DIR");
+        }
+    }
+
+    #[test]
+    fn test_render_options_after_subcommand() {
+        let cli = Cli::parse_from(["envsubst", "env-dump", "--env-file", "a.env"]);
+        assert_eq!(cli.env_file, vec![parse_env_file("a.env").unwrap()]);
+        let cli = Cli::parse_from([
+            "envsubst", "run", "--env-file", "a.env", "--no-cache", "-i", "t", "--", "cat",
+        ]);
+        assert_eq!(cli.env_file, vec![parse_env_file("a.env").unwrap()]);
+        assert!(cli.no_cache);
+        let cli = Cli::parse_from(["envsubst", "test", "cases", "--secret", "PASS", "--posix"]);
+        assert_eq!(cli.secret, vec!["PASS".to_string()]);
+        assert!(cli.posix);
+    }
+
+    #[test]
+    fn test_substitute_chunks_matches_sequential() {
+        unsafe {
+            env::set_var("CHUNK_A", "alpha");
+            env::set_var("CHUNK_B", "beta");
+        }
+        let input = "$CHUNK_A ${CHUNK_B\n}\n".repeat(50) + "${CHUNK_A|int";
+        let chunks = parallel::split_chunks(&input, 4, 16);
+        assert!(chunks.len() > 1);
+        let parallel = substitute_chunks(
+            &chunks,
+            None,
+            &Resolver::default(),
+            &Filters::default(),
+            &Limits::default(),
+        );
+        assert_eq!(parallel, substitute(&input, None));
+        unsafe {
+            env::remove_var("CHUNK_A");
+            env::remove_var("CHUNK_B");
+        }
+    }
 }
@@ -0,0 +1,86 @@
+//! Splitting a large input into chunks that can be substituted independently
+
+/// Inputs are not split into chunks smaller than this many bytes
+pub const MIN_CHUNK_SIZE: usize = 1 << 20;
+
+/// Split the input into at most `count` chunks of roughly equal size, each at
+/// least `min_size` bytes unless the input is smaller
+///
+/// Chunks end after a newline that is not inside a braced reference, so
+/// substituting the chunks separately gives the same result as substituting
+/// the whole input. Unbraced references never span lines.
+pub fn split_chunks(input: &str, count: usize, min_size: usize) -> Vec<&str> {
+    let count = count.min(input.len() / min_size.max(1)).max(1);
+    let target = input.len() / count;
+    let bytes = input.as_bytes();
+
+    let mut chunks = Vec::with_capacity(count);
+    let mut chunk_start = 0;
+    let mut pos = 0;
+    while pos < bytes.len() && chunks.len() + 1 < count {
+        match bytes[pos] {
+            b'$' if bytes.get(pos + 1) == Some(&b'{') => {
+                // Skip the whole reference; an unclosed one runs to the end
+                pos = match bytes[pos + 2..].iter().position(|&b| b == b'}') {
+                    Some(offset) => pos + 2 + offset + 1,
+                    None => bytes.len(),
+                };
+            }
+            b'\n' => {
+                pos += 1;
+                if pos - chunk_start >= target && pos < bytes.len() {
+                    chunks.push(&input[chunk_start..pos]);
+                    chunk_start = pos;
+                }
+            }
+            _ => pos += 1,
+        }
+    }
+    chunks.push(&input[chunk_start..]);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_small_input_is_one_chunk() {
+        assert_eq!(split_chunks("a\nb\n", 4, 100), vec!["a\nb\n"]);
+        assert_eq!(split_chunks("", 4, 100), vec![""]);
+    }
+
+    #[test]
+    fn test_split_at_line_boundaries() {
+        let input = "aaaa\nbbbb\ncccc\ndddd\n";
+        assert_eq!(
+            split_chunks(input, 4, 1),
+            vec!["aaaa\n", "bbbb\n", "cccc\n", "dddd\n"]
+        );
+        assert_eq!(split_chunks(input, 2, 1), vec!["aaaa\nbbbb\n", "cccc\ndddd\n"]);
+        assert_eq!(split_chunks(input, 2, 15), vec![input]);
+    }
+
+    #[test]
+    fn test_split_never_inside_braced_reference() {
+        let input = "aaaaaa\n${A\nB\nC}\ncccccc\ndddddd\n";
+        let chunks = split_chunks(input, 4, 1);
+        assert_eq!(chunks, vec!["aaaaaa\n", "${A\nB\nC}\n", "cccccc\n", "dddddd\n"]);
+
+        let unclosed = "aaaaaa\n${A\nbbbbbb\ncccccc\n";
+        assert_eq!(split_chunks(unclosed, 4, 1), vec!["aaaaaa\n", "${A\nbbbbbb\ncccccc\n"]);
+    }
+
+    #[test]
+    fn test_split_without_newlines() {
+        assert_eq!(split_chunks("one long line", 4, 1), vec!["one long line"]);
+    }
+
+    #[test]
+    fn test_chunks_cover_input() {
+        let input = "x $A ${B} $$ ${C|int}\n".repeat(100);
+        let chunks = split_chunks(&input, 7, 10);
+        assert_eq!(chunks.len(), 7);
+        assert_eq!(chunks.concat(), input);
+    }
+}
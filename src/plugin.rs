@@ -0,0 +1,273 @@
+//! Filters loaded from WebAssembly modules
+//!
+//! A plugin module exports its linear memory as `memory`, an allocator
+//! `alloc(len: i32) -> i32` and `reset()`. Every other exported function of
+//! type `(ptr: i32, len: i32) -> i64` is a filter: it receives the UTF-8 value
+//! at `ptr`/`len` and returns the location of its UTF-8 result packed as
+//! `ptr << 32 | len`. A filter rejects a value by trapping.
+//!
+//! Once the result of a filter has been read, `reset` is called and the
+//! plugin may reuse all memory allocated during the call. A filter call, its
+//! `alloc` included, may execute at most [`FUEL_PER_CALL`] units of fuel
+//! (roughly one per instruction), so a filter that never returns fails the
+//! render instead of hanging it; `reset` gets the same budget of its own.
+//! Linear memory may not grow beyond [`MAX_MEMORY`] bytes; a `memory.grow`
+//! past it traps.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use wasmi::{
+    Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TrapCode,
+    TypedFunc, ValType,
+};
+
+/// Fuel available to a single filter call
+pub const FUEL_PER_CALL: u64 = 100_000_000;
+
+/// Largest linear memory a plugin may have
+pub const MAX_MEMORY: usize = 64 << 20;
+
+/// A loaded plugin module and the filters it exports
+pub struct Plugin {
+    store: Mutex<Store<StoreLimits>>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    reset: TypedFunc<(), ()>,
+    filters: HashMap<String, TypedFunc<(i32, i32), i64>>,
+    fuel: u64,
+}
+
+impl Plugin {
+    /// Load and instantiate a plugin from a .wasm (or .wat) file
+    pub fn load(path: &Path) -> Result<Plugin, String> {
+        let error = |msg: String| format!("{}: {}", path.display(), msg);
+        let bytes = fs::read(path).map_err(|e| error(e.to_string()))?;
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes).map_err(|e| error(e.to_string()))?;
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(MAX_MEMORY)
+            .trap_on_grow_failure(true)
+            .build();
+        let mut store = Store::new(&engine, limits);
+        store.limiter(|limits| limits);
+        store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| error(e.to_string()))?;
+        let instance = Linker::new(&engine)
+            .instantiate_and_start(&mut store, &module)
+            .map_err(|e| error(e.to_string()))?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| error("plugin does not export 'memory'".to_string()))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|_| error("plugin does not export 'alloc(i32) -> i32'".to_string()))?;
+        let reset = instance
+            .get_typed_func::<(), ()>(&store, "reset")
+            .map_err(|_| error("plugin does not export 'reset()'".to_string()))?;
+
+        let mut filters = HashMap::new();
+        for export in module.exports() {
+            let is_filter = export.ty().func().is_some_and(|ty| {
+                ty.params() == [ValType::I32, ValType::I32] && ty.results() == [ValType::I64]
+            });
+            if is_filter {
+                let func = instance
+                    .get_typed_func(&store, export.name())
+                    .map_err(|e| error(e.to_string()))?;
+                filters.insert(export.name().to_string(), func);
+            }
+        }
+
+        Ok(Plugin {
+            store: Mutex::new(store),
+            memory,
+            alloc,
+            reset,
+            filters,
+            fuel: FUEL_PER_CALL,
+        })
+    }
+
+    /// Run the named filter on a value, or return None if the plugin has no such filter
+    pub fn call(&self, name: &str, value: &str) -> Option<Result<String, String>> {
+        let filter = self.filters.get(name)?;
+        Some(self.call_filter(filter, value))
+    }
+
+    fn call_filter(
+        &self,
+        filter: &TypedFunc<(i32, i32), i64>,
+        value: &str,
+    ) -> Result<String, String> {
+        let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+        let result = self.run_filter(&mut store, filter, value);
+        store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+        let reset = self.reset.call(&mut *store, ()).map_err(describe);
+        let output = result?;
+        reset?;
+        Ok(output)
+    }
+
+    /// Pass a value to a filter and copy out its result
+    fn run_filter(
+        &self,
+        store: &mut Store<StoreLimits>,
+        filter: &TypedFunc<(i32, i32), i64>,
+        value: &str,
+    ) -> Result<String, String> {
+        let len = i32::try_from(value.len()).map_err(|_| "value too large".to_string())?;
+
+        let ptr = self.alloc.call(&mut *store, len).map_err(describe)?;
+        self.memory
+            .write(&mut *store, ptr as u32 as usize, value.as_bytes())
+            .map_err(|e| e.to_string())?;
+
+        let packed = filter.call(&mut *store, (ptr, len)).map_err(describe)? as u64;
+        let start = (packed >> 32) as usize;
+        let len = (packed & 0xffff_ffff) as usize;
+        let data = self.memory.data(&*store);
+        let output = start
+            .checked_add(len)
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| "result lies outside the plugin's memory".to_string())?;
+        String::from_utf8(output.to_vec()).map_err(|_| "result is not valid UTF-8".to_string())
+    }
+}
+
+/// Describe an error from calling into a plugin
+fn describe(error: wasmi::Error) -> String {
+    match error.as_trap_code() {
+        Some(TrapCode::OutOfFuel) => "filter did not finish within its fuel limit".to_string(),
+        Some(TrapCode::GrowthOperationLimited) => {
+            format!("plugin memory would exceed the limit of {} bytes", MAX_MEMORY)
+        }
+        _ => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plugin with a bump allocator exporting `upper`, which uppercases ASCII
+    /// letters in place, `reject`, which always traps, `spin`, which never
+    /// returns, `wild`, which returns a result outside its memory, `next`,
+    /// which returns everything up to the allocator's position, and `hog`,
+    /// which grows its memory past the limit
+    const PLUGIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "reset")
+            (global.set $next (i32.const 1024)))
+          (func (export "upper") (param $ptr i32) (param $len i32) (result i64)
+            (local $i i32)
+            (local $c i32)
+            (block $done
+              (loop $loop
+                (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                (local.set $c (i32.load8_u (i32.add (local.get $ptr) (local.get $i))))
+                (if (i32.and (i32.ge_u (local.get $c) (i32.const 97))
+                             (i32.le_u (local.get $c) (i32.const 122)))
+                  (then (i32.store8 (i32.add (local.get $ptr) (local.get $i))
+                                    (i32.sub (local.get $c) (i32.const 32)))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $loop)))
+            (i64.or (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len))))
+          (func (export "reject") (param i32 i32) (result i64)
+            unreachable)
+          (func (export "spin") (param i32 i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0))
+          (func (export "wild") (param i32 i32) (result i64)
+            (i64.const 0xffff_ffff))
+          (func (export "next") (param i32 i32) (result i64)
+            (i64.extend_i32_u (global.get $next)))
+          (func (export "hog") (param i32 i32) (result i64)
+            (drop (memory.grow (i32.const 2048)))
+            (i64.const 0)))
+    "#;
+
+    fn load_test_plugin() -> Plugin {
+        let path = std::env::temp_dir().join(format!("envsubst-plugin-{}.wat", std::process::id()));
+        fs::write(&path, PLUGIN).unwrap();
+        let plugin = Plugin::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        plugin
+    }
+
+    #[test]
+    fn test_call_filter() {
+        let plugin = load_test_plugin();
+        assert_eq!(plugin.call("upper", "hello, world").unwrap().unwrap(), "HELLO, WORLD");
+        assert_eq!(plugin.call("upper", "again").unwrap().unwrap(), "AGAIN");
+    }
+
+    #[test]
+    fn test_unknown_and_failing_filters() {
+        let plugin = load_test_plugin();
+        assert!(plugin.call("alloc", "x").is_none());
+        assert!(plugin.call("missing", "x").is_none());
+        assert!(plugin.call("reject", "x").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_result_outside_memory() {
+        let plugin = load_test_plugin();
+        assert_eq!(
+            plugin.call("wild", "x").unwrap().unwrap_err(),
+            "result lies outside the plugin's memory"
+        );
+    }
+
+    #[test]
+    fn test_memory_is_reset_after_each_call() {
+        let plugin = load_test_plugin();
+        for _ in 0..3 {
+            assert_eq!(plugin.call("upper", "abc").unwrap().unwrap(), "ABC");
+            // Only the value passed to this call has been allocated
+            assert_eq!(plugin.call("next", "x").unwrap().unwrap().len(), 1025);
+        }
+    }
+
+    #[test]
+    fn test_fuel_limit() {
+        let mut plugin = load_test_plugin();
+        plugin.fuel = 10_000;
+        assert_eq!(
+            plugin.call("spin", "x").unwrap().unwrap_err(),
+            "filter did not finish within its fuel limit"
+        );
+        assert_eq!(plugin.call("upper", "ok").unwrap().unwrap(), "OK");
+        assert_eq!(plugin.call("next", "x").unwrap().unwrap().len(), 1025);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let plugin = load_test_plugin();
+        assert_eq!(
+            plugin.call("hog", "x").unwrap().unwrap_err(),
+            format!("plugin memory would exceed the limit of {} bytes", MAX_MEMORY)
+        );
+        assert_eq!(plugin.call("upper", "ok").unwrap().unwrap(), "OK");
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(Plugin::load(Path::new("/nonexistent/plugin.wasm")).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Java .properties and INI files: locating values and escaping substituted text
+
+use crate::Segment;
+
+/// Split .properties content into segments, marking property values as
+/// substitutable and everything else (comments, keys, separators) as verbatim
+///
+/// A value runs to the end of its logical line, so it includes any line
+/// continuations. Comment lines (starting with '#' or '!') never continue.
+pub fn split_properties(input: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let line_start = pos + leading_whitespace(&input[pos..]);
+        let rest = &input[line_start..];
+        let is_comment = rest.starts_with(['#', '!']);
+        let end = line_start + logical_line_len(rest, !is_comment);
+
+        if is_comment || line_start == end {
+            segments.push((&input[pos..end], false));
+        } else {
+            let key_end = line_start + key_len(&input[line_start..end]);
+            let value_start = key_end + separator_len(&input[key_end..end]);
+            segments.push((&input[pos..value_start], false));
+            segments.push((&input[value_start..end], true));
+        }
+
+        let terminator = line_terminator_len(&input[end..]);
+        segments.push((&input[end..end + terminator], false));
+        pos = end + terminator;
+    }
+
+    segments
+}
+
+/// Split INI content into segments, marking the values of `key = value` and
+/// `key: value` lines as substitutable and everything else as verbatim
+pub fn split_ini(input: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    for line in input.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let trimmed = content.trim_start();
+        let is_entry = !trimmed.is_empty() && !trimmed.starts_with(['[', ';', '#']);
+        match content.find(['=', ':']) {
+            Some(separator) if is_entry => {
+                let after = &content[separator + 1..];
+                let value_start = separator + 1 + (after.len() - after.trim_start().len());
+                segments.push((&line[..value_start], false));
+                segments.push((&content[value_start..], true));
+                segments.push((&line[content.len()..], false));
+            }
+            _ => segments.push((line, false)),
+        }
+    }
+    segments
+}
+
+/// Escape a value for a .properties file: backslashes and control characters
+/// are escaped, characters outside printable ASCII become \uXXXX escapes, and
+/// leading spaces are escaped so that they are not stripped on loading
+pub fn escape_properties(value: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut leading = true;
+    for ch in value.chars() {
+        match ch {
+            ' ' if leading => result.push_str("\\ "),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '\x0c' => result.push_str("\\f"),
+            ' '..='~' => result.push(ch),
+            _ => {
+                for unit in ch.encode_utf16(&mut [0; 2]) {
+                    result.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+        }
+        leading &= ch == ' ';
+    }
+    Ok(result)
+}
+
+/// INI has no escaping convention; a value must at least stay on its line
+pub fn escape_ini(value: &str) -> Result<String, String> {
+    if value.contains(['\n', '\r']) {
+        Err("line breaks cannot be represented in an INI value".to_string())
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// Length of the leading spaces, tabs and form feeds
+fn leading_whitespace(text: &str) -> usize {
+    text.len() - text.trim_start_matches([' ', '\t', '\x0c']).len()
+}
+
+/// Length of the logical line starting `text`, excluding its terminator
+/// A line ending in an odd number of backslashes continues onto the next
+/// line, unless `continues` is false
+fn logical_line_len(text: &str, continues: bool) -> usize {
+    let mut pos = 0;
+    loop {
+        let Some(offset) = text[pos..].find(['\n', '\r']) else {
+            return text.len();
+        };
+        let end = pos + offset;
+        let backslashes = text[pos..end].len() - text[pos..end].trim_end_matches('\\').len();
+        if !continues || backslashes.is_multiple_of(2) {
+            return end;
+        }
+        pos = end + line_terminator_len(&text[end..]);
+    }
+}
+
+/// Length of the line terminator at the start of `text`, if any
+fn line_terminator_len(text: &str) -> usize {
+    if text.starts_with("\r\n") {
+        2
+    } else if text.starts_with(['\n', '\r']) {
+        1
+    } else {
+        0
+    }
+}
+
+/// Length of the key at the start of a logical line: up to the first
+/// unescaped '=', ':' or whitespace
+fn key_len(line: &str) -> usize {
+    let mut chars = line.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '=' | ':' | ' ' | '\t' | '\x0c' => return i,
+            _ => {}
+        }
+    }
+    line.len()
+}
+
+/// Length of the separator after a key: whitespace and at most one '=' or ':'
+fn separator_len(text: &str) -> usize {
+    let mut len = leading_whitespace(text);
+    if text[len..].starts_with(['=', ':']) {
+        len += 1;
+        len += leading_whitespace(&text[len..]);
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(segments: &[Segment]) -> Vec<String> {
+        segments
+            .iter()
+            .filter(|(_, substitute)| *substitute)
+            .map(|(text, _)| text.to_string())
+            .collect()
+    }
+
+    fn joined(segments: &[Segment]) -> String {
+        segments.iter().map(|(text, _)| *text).collect()
+    }
+
+    #[test]
+    fn test_split_properties() {
+        let input = "# $COMMENT\n! $OTHER\n\n  db.url = jdbc:$HOST\nport:$PORT\r\n\
+                     name $NAME\nkey\\=with\\:seps=$V\nempty=\nmulti = a \\\n    $B\nlast";
+        let segments = split_properties(input);
+        assert_eq!(joined(&segments), input);
+        assert_eq!(
+            values(&segments),
+            vec!["jdbc:$HOST", "$PORT", "$NAME", "$V", "", "a \\\n    $B", ""]
+        );
+    }
+
+    #[test]
+    fn test_split_properties_escaped_backslash_does_not_continue() {
+        let input = "a=x\\\\\n$KEY=y\n# not continued \\\n$C=z";
+        let segments = split_properties(input);
+        assert_eq!(joined(&segments), input);
+        assert_eq!(values(&segments), vec!["x\\\\", "y", "z"]);
+    }
+
+    #[test]
+    fn test_split_ini() {
+        let input = "; $COMMENT\n[section $S]\nhost = $HOST\r\npath: /srv/$APP\nbare\n";
+        let segments = split_ini(input);
+        assert_eq!(joined(&segments), input);
+        assert_eq!(values(&segments), vec!["$HOST", "/srv/$APP"]);
+    }
+
+    #[test]
+    fn test_escape_properties() {
+        assert_eq!(escape_properties("plain=text:#!").unwrap(), "plain=text:#!");
+        assert_eq!(escape_properties("  a b").unwrap(), "\\ \\ a b");
+        assert_eq!(escape_properties("C:\\dir").unwrap(), "C:\\\\dir");
+        assert_eq!(escape_properties("a\nb\tc").unwrap(), "a\\nb\\tc");
+        assert_eq!(escape_properties("é€😀").unwrap(), "\\u00E9\\u20AC\\uD83D\\uDE00");
+    }
+
+    #[test]
+    fn test_escape_ini() {
+        assert_eq!(escape_ini("a = b ; c").unwrap(), "a = b ; c");
+        assert!(escape_ini("a\nb").is_err());
+    }
+}
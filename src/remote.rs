@@ -0,0 +1,93 @@
+//! Fetching templates from http(s) URLs
+//!
+//! A fetch fails if it takes longer than [`TIMEOUT`] in total or the body
+//! exceeds [`MAX_SIZE`] bytes, so a slow or misbehaving server cannot hang a
+//! render or exhaust memory. The body is fetched as sent, without HTTP content
+//! decoding, so input decompressed from it is held to the same size limit.
+
+use std::time::Duration;
+use ureq::Agent;
+
+/// Time allowed for the whole request, from connecting to reading the body
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest response body accepted
+pub const MAX_SIZE: u64 = 64 << 20;
+
+/// Fetch the body of `url`, failing on any status other than success
+pub fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    fetch_limited(url, MAX_SIZE)
+}
+
+fn fetch_limited(url: &str, max_size: u64) -> Result<Vec<u8>, String> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    let error = |e: ureq::Error| match e {
+        ureq::Error::BodyExceedsLimit(limit) => {
+            format!("{}: response is larger than {} bytes", url, limit)
+        }
+        e => format!("{}: {}", url, e),
+    };
+    let mut response = agent.get(url).call().map_err(error)?;
+    response
+        .body_mut()
+        .with_config()
+        .limit(max_size)
+        .read_to_vec()
+        .map_err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve one request on a local port with the given status and body
+    fn serve_once(status: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+        format!("http://{}/app.conf.tpl", addr)
+    }
+
+    #[test]
+    fn test_fetch() {
+        let url = serve_once("200 OK", "host=$HOST");
+        assert_eq!(fetch(&url).unwrap(), b"host=$HOST");
+    }
+
+    #[test]
+    fn test_fetch_error_status() {
+        let url = serve_once("404 Not Found", "missing");
+        assert_eq!(fetch(&url).unwrap_err(), format!("{}: http status: 404", url));
+    }
+
+    #[test]
+    fn test_fetch_too_large() {
+        let url = serve_once("200 OK", "host=$HOST");
+        assert_eq!(
+            fetch_limited(&url, 4).unwrap_err(),
+            format!("{}: response is larger than 4 bytes", url)
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Variable value lookup for a render
+
+use std::collections::HashMap;
+use std::env;
+
+/// Where the values of a namespace come from
+#[derive(Clone, Debug, PartialEq)]
+enum Source {
+    /// The process environment
+    Environment,
+    /// Values loaded from files
+    Values,
+    /// Values only visible through their own namespace
+    Named(HashMap<String, String>),
+}
+
+/// Looks up the values of variables referenced during a render
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    /// Values taking precedence over the process environment
+    values: HashMap<String, String>,
+    /// Whether variables missing from `values` are looked up in the process environment
+    use_environment: bool,
+    /// Names used in templates mapped to the names looked up instead
+    mapping: HashMap<String, String>,
+    /// Sources selectable with ${namespace.NAME}, or None when namespaced references are disabled
+    namespaces: Option<HashMap<String, Source>>,
+    /// Values looked up so far, or None when memoization is disabled
+    cache: Option<HashMap<String, String>>,
+}
+
+impl Resolver {
+    /// Create a resolver, remembering each lookup for its lifetime if `memoize` is set
+    pub fn new(memoize: bool) -> Self {
+        Resolver {
+            values: HashMap::new(),
+            use_environment: true,
+            mapping: HashMap::new(),
+            namespaces: None,
+            cache: memoize.then(HashMap::new),
+        }
+    }
+
+    /// Add values that take precedence over the process environment
+    /// Later values for the same name replace earlier ones
+    pub fn with_values(mut self, values: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.values.extend(values);
+        self
+    }
+
+    /// Look up `actual` whenever `template_name` is referenced
+    pub fn with_mapping(mut self, mapping: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.mapping.extend(mapping);
+        self
+    }
+
+    /// Enable ${namespace.NAME} references: `env` selects the process environment
+    /// and `dotenv` the values added with `with_values`
+    pub fn with_namespaces(mut self) -> Self {
+        let namespaces = self.namespaces.get_or_insert_with(HashMap::new);
+        namespaces.insert("env".to_string(), Source::Environment);
+        namespaces.insert("dotenv".to_string(), Source::Values);
+        self
+    }
+
+    /// Add values only visible as ${namespace.NAME}, enabling namespaced
+    /// references if they are not yet
+    /// Later values for the same name replace earlier ones; values for the
+    /// reserved `env` and `dotenv` namespaces are ignored
+    pub fn with_namespace(
+        mut self,
+        namespace: &str,
+        values: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        let source = self
+            .namespaces
+            .get_or_insert_with(HashMap::new)
+            .entry(namespace.to_string())
+            .or_insert_with(|| Source::Named(HashMap::new()));
+        if let Source::Named(named) = source {
+            named.extend(values);
+        }
+        self
+    }
+
+    /// Create a resolver that only sees the given values, ignoring the process environment
+    pub fn from_values(values: HashMap<String, String>, memoize: bool) -> Self {
+        Resolver {
+            values,
+            use_environment: false,
+            mapping: HashMap::new(),
+            namespaces: None,
+            cache: memoize.then(HashMap::new),
+        }
+    }
+
+    /// Get the value of a variable; unset variables resolve to the empty string
+    /// Fails if the name refers to an unknown namespace
+    pub fn lookup(&mut self, name: &str) -> Result<String, String> {
+        if let Some(value) = self.cache.as_ref().and_then(|cache| cache.get(name)) {
+            return Ok(value.clone());
+        }
+        let value = self.fetch(name)?;
+        if let Some(cache) = &mut self.cache {
+            cache.insert(name.to_string(), value.clone());
+        }
+        Ok(value)
+    }
+
+    fn fetch(&self, name: &str) -> Result<String, String> {
+        let name = self.mapping.get(name).map_or(name, String::as_str);
+        if let Some(namespaces) = &self.namespaces
+            && let Some((namespace, var_name)) = name.split_once('.')
+        {
+            return match namespaces.get(namespace) {
+                Some(source) => Ok(self.fetch_from(source, var_name)),
+                None => Err(format!(
+                    "unknown namespace '{}' in reference to '{}'",
+                    namespace, name
+                )),
+            };
+        }
+        Ok(self
+            .values
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| self.fetch_from(&Source::Environment, name)))
+    }
+
+    fn fetch_from(&self, source: &Source, name: &str) -> String {
+        match source {
+            Source::Environment if self.use_environment => env::var(name).unwrap_or_default(),
+            Source::Environment => String::new(),
+            Source::Values => self.values.get(name).cloned().unwrap_or_default(),
+            Source::Named(values) => values.get(name).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver::new(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_unset_is_empty() {
+        let mut resolver = Resolver::default();
+        assert_eq!(resolver.lookup("RESOLVER_UNSET_12345").unwrap(), "");
+    }
+
+    #[test]
+    fn test_memoized_lookup_is_stable() {
+        unsafe {
+            env::set_var("RESOLVER_MEMO", "first");
+        }
+        let mut resolver = Resolver::new(true);
+        assert_eq!(resolver.lookup("RESOLVER_MEMO").unwrap(), "first");
+        unsafe {
+            env::set_var("RESOLVER_MEMO", "second");
+        }
+        assert_eq!(resolver.lookup("RESOLVER_MEMO").unwrap(), "first");
+        unsafe {
+            env::remove_var("RESOLVER_MEMO");
+        }
+    }
+
+    #[test]
+    fn test_lookup_without_memoization() {
+        unsafe {
+            env::set_var("RESOLVER_NO_MEMO", "first");
+        }
+        let mut resolver = Resolver::new(false);
+        assert_eq!(resolver.lookup("RESOLVER_NO_MEMO").unwrap(), "first");
+        unsafe {
+            env::set_var("RESOLVER_NO_MEMO", "second");
+        }
+        assert_eq!(resolver.lookup("RESOLVER_NO_MEMO").unwrap(), "second");
+        unsafe {
+            env::remove_var("RESOLVER_NO_MEMO");
+        }
+    }
+
+    #[test]
+    fn test_lookup_from_values_ignores_environment() {
+        unsafe {
+            env::set_var("RESOLVER_FIXED", "from env");
+        }
+        let values = HashMap::from([("RESOLVER_OTHER".to_string(), "fixed".to_string())]);
+        let mut resolver = Resolver::from_values(values, true);
+        assert_eq!(resolver.lookup("RESOLVER_OTHER").unwrap(), "fixed");
+        assert_eq!(resolver.lookup("RESOLVER_FIXED").unwrap(), "");
+        unsafe {
+            env::remove_var("RESOLVER_FIXED");
+        }
+    }
+
+    #[test]
+    fn test_with_values_overrides_environment() {
+        unsafe {
+            env::set_var("RESOLVER_LAYERED", "from env");
+            env::set_var("RESOLVER_LAYERED_KEPT", "kept");
+        }
+        let mut resolver = Resolver::new(true).with_values([
+            ("RESOLVER_LAYERED".to_string(), "first".to_string()),
+            ("RESOLVER_LAYERED".to_string(), "second".to_string()),
+        ]);
+        assert_eq!(resolver.lookup("RESOLVER_LAYERED").unwrap(), "second");
+        assert_eq!(resolver.lookup("RESOLVER_LAYERED_KEPT").unwrap(), "kept");
+        unsafe {
+            env::remove_var("RESOLVER_LAYERED");
+            env::remove_var("RESOLVER_LAYERED_KEPT");
+        }
+    }
+
+    #[test]
+    fn test_mapping() {
+        let values = HashMap::from([
+            ("APP_DB_HOST".to_string(), "db.internal".to_string()),
+            ("DB_HOST".to_string(), "unused".to_string()),
+        ]);
+        let mut resolver = Resolver::from_values(values, true)
+            .with_mapping([("DB_HOST".to_string(), "APP_DB_HOST".to_string())]);
+        assert_eq!(resolver.lookup("DB_HOST").unwrap(), "db.internal");
+        assert_eq!(resolver.lookup("APP_DB_HOST").unwrap(), "db.internal");
+    }
+
+    #[test]
+    fn test_namespaces() {
+        unsafe {
+            env::set_var("RESOLVER_NS", "from env");
+        }
+        let mut resolver = Resolver::new(true)
+            .with_values([("RESOLVER_NS".to_string(), "from file".to_string())])
+            .with_namespaces();
+        assert_eq!(resolver.lookup("RESOLVER_NS").unwrap(), "from file");
+        assert_eq!(resolver.lookup("env.RESOLVER_NS").unwrap(), "from env");
+        assert_eq!(resolver.lookup("dotenv.RESOLVER_NS").unwrap(), "from file");
+        assert_eq!(resolver.lookup("dotenv.RESOLVER_NS_UNSET").unwrap(), "");
+        assert_eq!(
+            resolver.lookup("secrets.DB_PASS").unwrap_err(),
+            "unknown namespace 'secrets' in reference to 'secrets.DB_PASS'"
+        );
+        unsafe {
+            env::remove_var("RESOLVER_NS");
+        }
+    }
+
+    #[test]
+    fn test_namespaces_from_values() {
+        unsafe {
+            env::set_var("RESOLVER_NS_FIXED", "from env");
+        }
+        let values = HashMap::from([("RESOLVER_NS_FIXED".to_string(), "fixed".to_string())]);
+        let mut resolver = Resolver::from_values(values, true).with_namespaces();
+        assert_eq!(resolver.lookup("dotenv.RESOLVER_NS_FIXED").unwrap(), "fixed");
+        assert_eq!(resolver.lookup("env.RESOLVER_NS_FIXED").unwrap(), "");
+        unsafe {
+            env::remove_var("RESOLVER_NS_FIXED");
+        }
+    }
+
+    #[test]
+    fn test_named_namespaces() {
+        let mut resolver = Resolver::new(true)
+            .with_namespaces()
+            .with_namespace("secrets", [("RESOLVER_NAMED_PASS".to_string(), "first".to_string())])
+            .with_namespace("secrets", [("RESOLVER_NAMED_PASS".to_string(), "second".to_string())])
+            .with_namespace("app", [("RESOLVER_NAMED_HOST".to_string(), "db.internal".to_string())]);
+        assert_eq!(resolver.lookup("secrets.RESOLVER_NAMED_PASS").unwrap(), "second");
+        assert_eq!(resolver.lookup("app.RESOLVER_NAMED_HOST").unwrap(), "db.internal");
+        assert_eq!(resolver.lookup("app.RESOLVER_NAMED_PASS").unwrap(), "");
+        assert_eq!(resolver.lookup("RESOLVER_NAMED_PASS").unwrap(), "");
+        assert_eq!(resolver.lookup("dotenv.RESOLVER_NAMED_PASS").unwrap(), "");
+    }
+
+    #[test]
+    fn test_dotted_names_without_namespaces() {
+        let mut resolver = Resolver::new(true);
+        assert_eq!(resolver.lookup("env.HOME").unwrap(), "");
+    }
+}
@@ -0,0 +1,166 @@
+//! The run subcommand: feed rendered output to a child process
+
+use clap::ValueEnum;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Ways of handing the rendered output to the child
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Via {
+    /// Write the output to the child's stdin
+    Stdin,
+    /// Write the output to a private temporary file, in $XDG_RUNTIME_DIR if
+    /// set, and remove it when the child exits
+    File,
+}
+
+/// Run `command` with the rendered `output` and return the exit code to forward
+pub fn run(output: &str, via: Via, command: &[String]) -> Result<i32, String> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| "no command given".to_string())?;
+    if via == Via::File && !args.iter().any(|arg| arg.contains("{}")) {
+        return Err(
+            "with --via file, the command needs a \"{}\" argument for the file path".to_string(),
+        );
+    }
+
+    let status = match via {
+        Via::Stdin => {
+            let mut child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("{}: {}", program, e))?;
+            let mut stdin = child.stdin.take().expect("child stdin is piped");
+            // A child that exits without reading its input closes the pipe;
+            // its exit status is what matters then, not the write error
+            let _ = stdin.write_all(output.as_bytes());
+            drop(stdin);
+            child.wait().map_err(|e| format!("{}: {}", program, e))?
+        }
+        Via::File => {
+            let (path, mut file) = create_private_file()?;
+            let result = file
+                .write_all(output.as_bytes())
+                .map_err(|e| format!("{}: {}", path.display(), e))
+                .and_then(|_| {
+                    let path_arg = path.to_string_lossy();
+                    Command::new(program)
+                        .args(args.iter().map(|arg| arg.replace("{}", &path_arg)))
+                        .status()
+                        .map_err(|e| format!("{}: {}", program, e))
+                });
+            let _ = fs::remove_file(&path);
+            result?
+        }
+    };
+
+    Ok(exit_code(status))
+}
+
+/// Create a new file readable only by the current user in the private directory
+/// The output is never synced, so it need not reach stable storage
+fn create_private_file() -> Result<(PathBuf, File), String> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let dir = private_dir(std::env::var_os("XDG_RUNTIME_DIR"));
+    let path = dir.join(format!("envsubst-{}-{}", std::process::id(), nanos));
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok((path, file))
+}
+
+/// The directory for the private file: the runtime directory given as
+/// `runtime_dir` (from $XDG_RUNTIME_DIR) if it is an absolute path, else the
+/// temp directory
+fn private_dir(runtime_dir: Option<OsString>) -> PathBuf {
+    match runtime_dir {
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+        _ => std::env::temp_dir(),
+    }
+}
+
+/// Map a child's exit status to our own exit code, shell style
+fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Vec<String> {
+        vec!["sh".to_string(), "-c".to_string(), script.to_string()]
+    }
+
+    #[test]
+    fn test_run_via_stdin() {
+        let code = run("hello", Via::Stdin, &sh("test \"$(cat)\" = hello")).unwrap();
+        assert_eq!(code, 0);
+    }
+
+    #[test]
+    fn test_run_via_file() {
+        let mut command = sh("test \"$(cat \"$1\")\" = hello");
+        command.push("sh".to_string());
+        command.push("{}".to_string());
+        assert_eq!(run("hello", Via::File, &command).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_run_forwards_exit_status() {
+        assert_eq!(run("", Via::Stdin, &sh("exit 3")).unwrap(), 3);
+        let mut command = sh("exit 4");
+        command.push("{}".to_string());
+        assert_eq!(run("", Via::File, &command).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_run_via_file_needs_path_argument() {
+        assert_eq!(
+            run("hello", Via::File, &sh("cat")).unwrap_err(),
+            "with --via file, the command needs a \"{}\" argument for the file path"
+        );
+    }
+
+    #[test]
+    fn test_run_missing_program() {
+        let command = vec!["envsubst-no-such-program".to_string()];
+        assert!(run("", Via::Stdin, &command).is_err());
+    }
+
+    #[test]
+    fn test_private_dir() {
+        let runtime = Some(OsString::from("/run/user/1000"));
+        assert_eq!(private_dir(runtime), PathBuf::from("/run/user/1000"));
+        assert_eq!(private_dir(None), std::env::temp_dir());
+        assert_eq!(private_dir(Some(OsString::new())), std::env::temp_dir());
+        assert_eq!(private_dir(Some(OsString::from("relative"))), std::env::temp_dir());
+    }
+}
@@ -0,0 +1,256 @@
+//! Splitting a template into literal text and variable references
+
+use std::ops::Range;
+
+/// A piece of a template, as yielded by [`Tokenizer`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event<'a> {
+    /// Literal text, including any '$' that does not start a reference
+    Text(&'a str),
+    /// A variable reference
+    Var {
+        /// The name as written; for ${...} it is everything before the first
+        /// '|', so it may be empty or not a valid name
+        name: &'a str,
+        /// How the reference was written
+        syntax: Syntax<'a>,
+        /// Byte range of the whole reference in the input, '$' included
+        span: Range<usize>,
+    },
+}
+
+/// The syntax of a variable reference
+#[derive(Clone, Debug, PartialEq)]
+pub enum Syntax<'a> {
+    /// $NAME
+    Simple,
+    /// ${NAME} or ${NAME|filter|...}; a reference missing its '}' runs to
+    /// the end of the input
+    Braced {
+        /// Filters in the order written, with surrounding whitespace trimmed
+        filters: Vec<&'a str>,
+    },
+}
+
+impl<'a> Syntax<'a> {
+    /// The filters of the reference; a $NAME reference has none
+    pub fn filters(&self) -> &[&'a str] {
+        match self {
+            Syntax::Simple => &[],
+            Syntax::Braced { filters } => filters,
+        }
+    }
+}
+
+/// An iterator over the [`Event`]s of a template
+///
+/// Text between references is yielded as a single event, so adjacent events
+/// are never both [`Event::Text`]. Concatenating the text of every event and
+/// the spans of every reference gives back the input.
+#[derive(Clone, Debug)]
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Tokenize `input` from its start
+    pub fn new(input: &'a str) -> Self {
+        Tokenizer { input, pos: 0 }
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Event<'a>> {
+        if self.pos >= self.input.len() {
+            return None;
+        }
+        let start = self.pos;
+        let mut search = start;
+        while let Some(offset) = self.input[search..].find('$') {
+            let dollar = search + offset;
+            if dollar > start {
+                if parse_reference(self.input, dollar).is_some() {
+                    self.pos = dollar;
+                    return Some(Event::Text(&self.input[start..dollar]));
+                }
+            } else if let Some(event) = parse_reference(self.input, dollar) {
+                if let Event::Var { span, .. } = &event {
+                    self.pos = span.end;
+                }
+                return Some(event);
+            }
+            search = dollar + 1;
+        }
+        self.pos = self.input.len();
+        Some(Event::Text(&self.input[start..]))
+    }
+}
+
+/// Parse the reference starting at the '$' at byte `dollar`, if there is one
+fn parse_reference(input: &str, dollar: usize) -> Option<Event<'_>> {
+    let rest = &input[dollar + 1..];
+    let first = rest.chars().next()?;
+    if first == '{' {
+        let content_start = dollar + 2;
+        let (content_end, end) = match input[content_start..].find('}') {
+            Some(offset) => (content_start + offset, content_start + offset + 1),
+            None => (input.len(), input.len()),
+        };
+        let mut parts = input[content_start..content_end].split('|');
+        let name = parts.next().unwrap_or_default();
+        let filters = parts.map(str::trim).collect();
+        Some(Event::Var {
+            name,
+            syntax: Syntax::Braced { filters },
+            span: dollar..end,
+        })
+    } else if is_var_start(first) {
+        let len = rest.find(|ch| !is_var_char(ch)).unwrap_or(rest.len());
+        Some(Event::Var {
+            name: &rest[..len],
+            syntax: Syntax::Simple,
+            span: dollar..dollar + 1 + len,
+        })
+    } else {
+        None
+    }
+}
+
+/// Check if a string is a valid variable name
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(is_var_start) && chars.all(is_var_char)
+}
+
+/// Check if a character can start a variable name (letter or underscore)
+fn is_var_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
+/// Check if a character can be part of a variable name (letter, digit, or underscore)
+fn is_var_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(input: &str) -> Vec<Event<'_>> {
+        Tokenizer::new(input).collect()
+    }
+
+    #[test]
+    fn test_text_and_simple_references() {
+        assert_eq!(
+            events("Hello $USER, bye"),
+            vec![
+                Event::Text("Hello "),
+                Event::Var {
+                    name: "USER",
+                    syntax: Syntax::Simple,
+                    span: 6..11,
+                },
+                Event::Text(", bye"),
+            ]
+        );
+        assert_eq!(events(""), vec![]);
+    }
+
+    #[test]
+    fn test_braced_reference_with_filters() {
+        assert_eq!(
+            events("${PORT|int | port}"),
+            vec![Event::Var {
+                name: "PORT",
+                syntax: Syntax::Braced {
+                    filters: vec!["int", "port"],
+                },
+                span: 0..18,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_stray_dollars_are_text() {
+        assert_eq!(events("$ $1 cost$"), vec![Event::Text("$ $1 cost$")]);
+        assert_eq!(
+            events("$$A"),
+            vec![
+                Event::Text("$"),
+                Event::Var {
+                    name: "A",
+                    syntax: Syntax::Simple,
+                    span: 1..3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unclosed_and_empty_braces() {
+        assert_eq!(
+            events("a ${} ${B"),
+            vec![
+                Event::Text("a "),
+                Event::Var {
+                    name: "",
+                    syntax: Syntax::Braced { filters: vec![] },
+                    span: 2..5,
+                },
+                Event::Text(" "),
+                Event::Var {
+                    name: "B",
+                    syntax: Syntax::Braced { filters: vec![] },
+                    span: 6..9,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans_cover_input() {
+        let input = "é $A${B|int}\n$ ${C";
+        let mut rebuilt = String::new();
+        for event in Tokenizer::new(input) {
+            match event {
+                Event::Text(text) => rebuilt.push_str(text),
+                Event::Var { span, .. } => rebuilt.push_str(&input[span]),
+            }
+        }
+        assert_eq!(rebuilt, input);
+    }
+
+    #[test]
+    fn test_is_var_start() {
+        assert!(is_var_start('a'));
+        assert!(is_var_start('Z'));
+        assert!(is_var_start('_'));
+        assert!(!is_var_start('1'));
+        assert!(!is_var_start('-'));
+        assert!(!is_var_start('$'));
+    }
+
+    #[test]
+    fn test_is_var_char() {
+        assert!(is_var_char('a'));
+        assert!(is_var_char('Z'));
+        assert!(is_var_char('_'));
+        assert!(is_var_char('0'));
+        assert!(is_var_char('9'));
+        assert!(!is_var_char('-'));
+        assert!(!is_var_char('$'));
+        assert!(!is_var_char(' '));
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("_A1"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("1A"));
+        assert!(!is_valid_name("A-B"));
+    }
+}
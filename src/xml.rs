@@ -0,0 +1,170 @@
+//! XML documents: locating text and attribute values and escaping substituted text
+
+use crate::Segment;
+
+/// Split an XML document into segments, marking text content and attribute
+/// values as substitutable
+///
+/// Markup, comments, CDATA sections, processing instructions and the
+/// document type declaration are kept verbatim.
+pub fn split_xml(input: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let Some(offset) = input[pos..].find('<') else {
+            segments.push((&input[pos..], true));
+            break;
+        };
+        if offset > 0 {
+            segments.push((&input[pos..pos + offset], true));
+        }
+        pos += offset;
+
+        let rest = &input[pos..];
+        let verbatim_end = |terminator: &str| {
+            rest.find(terminator)
+                .map_or(input.len(), |i| pos + i + terminator.len())
+        };
+        if rest.starts_with("<!--") {
+            let end = verbatim_end("-->");
+            segments.push((&input[pos..end], false));
+            pos = end;
+        } else if rest.starts_with("<![CDATA[") {
+            let end = verbatim_end("]]>");
+            segments.push((&input[pos..end], false));
+            pos = end;
+        } else if rest.starts_with("<?") {
+            let end = verbatim_end("?>");
+            segments.push((&input[pos..end], false));
+            pos = end;
+        } else if rest.starts_with("<!") {
+            let end = pos + declaration_len(rest);
+            segments.push((&input[pos..end], false));
+            pos = end;
+        } else {
+            pos = split_tag(input, pos, &mut segments);
+        }
+    }
+
+    segments
+}
+
+/// Split the tag starting at `start`, returning the position after it
+fn split_tag<'a>(input: &'a str, start: usize, segments: &mut Vec<Segment<'a>>) -> usize {
+    let mut markup_start = start;
+    let mut pos = start;
+
+    while let Some(ch) = input[pos..].chars().next() {
+        match ch {
+            '>' => {
+                segments.push((&input[markup_start..pos + 1], false));
+                return pos + 1;
+            }
+            '"' | '\'' => {
+                segments.push((&input[markup_start..pos + 1], false));
+                let value_start = pos + 1;
+                let Some(len) = input[value_start..].find(ch) else {
+                    segments.push((&input[value_start..], false));
+                    return input.len();
+                };
+                segments.push((&input[value_start..value_start + len], true));
+                markup_start = value_start + len;
+                pos = markup_start + 1;
+            }
+            _ => pos += ch.len_utf8(),
+        }
+    }
+
+    segments.push((&input[markup_start..], false));
+    input.len()
+}
+
+/// Length of a <!...> declaration, which may contain a bracketed internal
+/// subset and quoted literals
+fn declaration_len(text: &str) -> usize {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, ch) in text.char_indices() {
+        match (quote, ch) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            (None, '>') if depth <= 0 => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Escape a value for XML text or a quoted attribute value
+/// Characters XML 1.0 does not allow at all are an error
+pub fn escape_xml(value: &str) -> Result<String, String> {
+    let mut result = String::new();
+    for ch in value.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            '\t' | '\n' | '\r' => result.push(ch),
+            c if c < ' ' || c == '\u{fffe}' || c == '\u{ffff}' => {
+                return Err(format!("character U+{:04X} is not allowed in XML", c as u32));
+            }
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(segments: &[Segment]) -> Vec<String> {
+        segments
+            .iter()
+            .filter(|(text, substitute)| *substitute && !text.trim().is_empty())
+            .map(|(text, _)| text.to_string())
+            .collect()
+    }
+
+    fn joined(segments: &[Segment]) -> String {
+        segments.iter().map(|(text, _)| *text).collect()
+    }
+
+    #[test]
+    fn test_split_xml() {
+        let input = "<?xml version=\"1.0\"?>\n\
+                     <!DOCTYPE c [<!ENTITY e \"$ENT>\">]>\n\
+                     <!-- $COMMENT -->\n\
+                     <$TAG a=\"$A\" b='x $B'>text $T<![CDATA[$CDATA]]></$TAG>\n\
+                     <empty c=\"$C\"/>";
+        let segments = split_xml(input);
+        assert_eq!(joined(&segments), input);
+        assert_eq!(values(&segments), vec!["$A", "x $B", "text $T", "$C"]);
+    }
+
+    #[test]
+    fn test_split_xml_unterminated() {
+        for input in ["<a b=\"$X", "<a $X", "<!-- $X", "text $X"] {
+            let segments = split_xml(input);
+            assert_eq!(joined(&segments), input);
+        }
+        assert_eq!(values(&split_xml("<a b=\"$X")), Vec::<String>::new());
+        assert_eq!(values(&split_xml("text $X")), vec!["text $X"]);
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml("a & b < c > d \"e\" 'f'").unwrap(),
+            "a &amp; b &lt; c &gt; d &quot;e&quot; &apos;f&apos;"
+        );
+        assert_eq!(escape_xml("line\nbreak\té").unwrap(), "line\nbreak\té");
+        assert!(escape_xml("bell\u{7}").is_err());
+    }
+}